@@ -0,0 +1,256 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+/// The compression formats read and written by every command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Recognises `.gz`, `.zst` and `.bz2` style extensions.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zst" | "zstd" => Some(Self::Zstd),
+            "bz2" | "bzip2" => Some(Self::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// The extension appended to compressed file names.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gz",
+            Self::Zstd => "zst",
+            Self::Bzip2 => "bz2",
+        }
+    }
+
+    /// Recognises the magic bytes at the start of a compressed stream.
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0x1f, 0x8b, ..] => Some(Self::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Self::Zstd),
+            [b'B', b'Z', b'h', ..] => Some(Self::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Detects the compression of `reader` from the extension of `path`, falling back to peeking
+    /// at its first bytes. Nothing is consumed from `reader`.
+    pub fn detect<R: BufRead>(
+        reader: &mut R,
+        path: Option<&Path>,
+    ) -> std::io::Result<Option<Self>> {
+        if let Some(compression) = path.and_then(Self::from_extension) {
+            return Ok(Some(compression));
+        }
+        Ok(Self::from_magic(reader.fill_buf()?))
+    }
+
+    /// Wraps `reader` in a streaming decoder for this format.
+    pub fn decoder<'a, R: BufRead + Send + 'a>(
+        self,
+        reader: R,
+    ) -> std::io::Result<Box<dyn BufRead + Send + 'a>> {
+        Ok(match self {
+            // Multi-member gzip files, as written by `cat a.gz b.gz`, are read to the end
+            Self::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+            Self::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+            Self::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+        })
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            "bzip2" | "bz2" => Ok(Self::Bzip2),
+            s => Err(format!(
+                "Unknown compression {s}, expected gzip, zstd or bzip2"
+            )),
+        }
+    }
+}
+
+/// A writer that compresses everything written to it, or passes it straight through.
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Bzip2(bzip2::write::BzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Wraps `writer` in an encoder for `compression`. Without a `level` each codec uses its own
+    /// default; gzip and bzip2 accept levels 1-9 and zstd accepts 1-22.
+    pub fn new(
+        writer: W,
+        compression: Option<Compression>,
+        level: Option<u32>,
+    ) -> std::io::Result<Self> {
+        let max_level = match compression {
+            Some(Compression::Zstd) => 22,
+            _ => 9,
+        };
+        if let Some(level) = level.filter(|level| !(1..=max_level).contains(level)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Compression level {level} is outside 1-{max_level}"),
+            ));
+        }
+        Ok(match compression {
+            None => Self::Plain(writer),
+            Some(Compression::Gzip) => Self::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                level.map_or(flate2::Compression::default(), flate2::Compression::new),
+            )),
+            Some(Compression::Zstd) => Self::Zstd(zstd::Encoder::new(
+                writer,
+                level.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |level| level as i32),
+            )?),
+            Some(Compression::Bzip2) => Self::Bzip2(bzip2::write::BzEncoder::new(
+                writer,
+                level.map_or(bzip2::Compression::default(), bzip2::Compression::new),
+            )),
+        })
+    }
+
+    /// The writer the compressed bytes are written to.
+    pub fn get_ref(&self) -> &W {
+        match self {
+            Self::Plain(writer) => writer,
+            Self::Gzip(encoder) => encoder.get_ref(),
+            Self::Zstd(encoder) => encoder.get_ref(),
+            Self::Bzip2(encoder) => encoder.get_ref(),
+        }
+    }
+
+    /// Ends the compressed stream and flushes it. Nothing may be written afterwards.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        // The encoders refuse to be flushed once finished, so only the writer below is flushed
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()
+            }
+            Self::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()
+            }
+            Self::Bzip2(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+            Self::Bzip2(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+            Self::Bzip2(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Decompresses `reader` while it is read if it is compressed, otherwise returns it unchanged.
+pub fn decompress<'a, R: BufRead + Send + 'a>(
+    mut reader: R,
+    path: Option<&Path>,
+) -> std::io::Result<Box<dyn BufRead + Send + 'a>> {
+    match Compression::detect(&mut reader, path)? {
+        Some(compression) => {
+            log::debug!("Decompressing {compression:?} input");
+            compression.decoder(reader)
+        }
+        None => Ok(Box::new(reader)),
+    }
+}
+
+/// Strips a compression extension from `path`, so `a.json.gz` is named like `a.json`.
+pub fn strip_extension(path: &Path) -> &Path {
+    match Compression::from_extension(path) {
+        Some(_) => Path::new(path.file_stem().unwrap_or_default()),
+        None => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_decompress_detects_magic_bytes() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(b"{\"a\":1}\n").unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let zstded = zstd::encode_all(&b"{\"b\":2}\n"[..], 0).unwrap();
+
+        for (bytes, expected) in [(gzipped, "{\"a\":1}\n"), (zstded, "{\"b\":2}\n")] {
+            let mut decoded = String::new();
+            decompress(&bytes[..], None)
+                .unwrap()
+                .read_to_string(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn test_encoder_round_trip() {
+        for compression in [Compression::Gzip, Compression::Zstd, Compression::Bzip2] {
+            let mut encoder = Encoder::new(Vec::new(), Some(compression), Some(3)).unwrap();
+            encoder.write_all(b"{\"d\":4}\n").unwrap();
+            encoder.finish().unwrap();
+            let bytes = encoder.get_ref();
+            assert_eq!(Compression::from_magic(bytes), Some(compression));
+
+            let mut decoded = String::new();
+            decompress(&bytes[..], None)
+                .unwrap()
+                .read_to_string(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, "{\"d\":4}\n");
+        }
+    }
+
+    #[test]
+    fn test_encoder_rejects_out_of_range_level() {
+        assert!(Encoder::new(Vec::new(), Some(Compression::Gzip), Some(10)).is_err());
+        assert!(Encoder::new(Vec::new(), Some(Compression::Zstd), Some(19)).is_ok());
+    }
+
+    #[test]
+    fn test_decompress_passes_plain_input_through() {
+        let mut decoded = String::new();
+        decompress(&b"{\"c\":3}"[..], None)
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "{\"c\":3}");
+    }
+}
@@ -1,47 +1,183 @@
 mod directory;
 mod file;
+mod glob;
+mod keys;
+mod policy;
+mod records;
 mod stdin;
 
 pub use directory::InputDirectory;
 use eyre::{eyre, Report, Result};
+pub use file::stream_entries;
 use file::InputFile;
-use serde_json::Value;
-use std::{collections::HashMap, ops::Deref, path::PathBuf, sync::Arc};
+pub use glob::{FileFilter, Glob, IGNORE_FILE};
+pub use keys::{FileKey, KeyField};
+pub use policy::{ErrorPolicy, OnError};
+pub use records::{count_lines, RecordReader, RECORD_SEPARATOR};
+use serde_json::{value::RawValue, Value};
+use std::{
+    ops::Deref,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
 use stdin::InputStdin;
 
+/// Locates a value in the top-level JSON document: a key of an object root, or the position of
+/// an element in an array root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntryKey {
+    Key(String),
+    Index(usize),
+}
+
+/// How a directory input reads its subdirectories
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Recursion {
+    /// How many levels of subdirectories to read, or `None` for no limit
+    pub max_depth: Option<usize>,
+    /// Whether `a/b.json` becomes the key `a/b` instead of `b` nested inside `a`
+    pub flatten: bool,
+}
+
+/// A lazily-read sequence of `(key, value)` entries
+pub type Entries<'a, V = Value> = Box<dyn Iterator<Item = Result<(String, V)>> + 'a>;
+
+/// The order `JsonSource::entries` reads entries in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Order {
+    /// The order the input lists them in
+    #[default]
+    Listed,
+    /// Alphabetical key order
+    Sorted,
+    /// Alphabetical key order, except that keys which are plain indexes, like the ones `split`
+    /// gives array elements, come first in numeric order
+    Indexed,
+}
+
+impl Order {
+    /// Whether entries are put in order by key at all.
+    pub fn is_sorted(self) -> bool {
+        self != Order::Listed
+    }
+
+    /// What `name` is compared by to put it in order.
+    pub fn key(self, name: &str) -> (bool, usize, String) {
+        if self == Order::Indexed && !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) {
+            let digits = name.trim_start_matches('0');
+            return (false, digits.len(), digits.to_string());
+        }
+        (true, 0, name.to_string())
+    }
+
+    /// Puts `entries` in this order, which means reading all of them first when it sorts.
+    pub fn sort<'a, V: 'a>(self, entries: Entries<'a, V>) -> Result<Entries<'a, V>> {
+        if !self.is_sorted() {
+            return Ok(entries);
+        }
+        let mut entries = entries.collect::<Result<Vec<_>>>()?;
+        entries.sort_by_cached_key(|(key, _)| self.key(key));
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+}
+
 pub trait JsonSource: Send + Sync {
-    fn get_entries(&self, sort: bool) -> Vec<(String, Value)>;
-    fn read_entry(&self) -> Result<(String, Value)>;
+    /// Accepts JSON5 and JSONC, such as comments and trailing commas, in place of strict JSON.
+    fn set_lenient(&mut self, lenient: bool);
+    /// Reads subdirectories as `recursion` describes, rather than skipping them. Only directory
+    /// inputs have subdirectories, so by default this does nothing.
+    fn set_recursion(&mut self, _recursion: Option<Recursion>) {}
+    /// Reads only the files of a directory input that `filter` includes. Other inputs are read
+    /// whole, so by default this does nothing.
+    fn set_file_filter(&mut self, _filter: FileFilter) {}
+    /// Handles the files of a directory or file input that cannot be read as `policy` says, rather
+    /// than failing on the first one. Stdin fails as a whole, so by default this does nothing.
+    fn set_error_policy(&mut self, _policy: ErrorPolicy) {}
+    /// Derives the key of each file read as an entry as `keys` says. Stdin entries carry their
+    /// own keys, so by default this does nothing.
+    fn set_file_key(&mut self, _keys: FileKey) {}
+    /// Iterates over the entries without collecting them, reading ahead only a bounded amount.
+    fn entries(&self, order: Order) -> Result<Entries<'_>>;
+    /// Like `entries`, but keeps each value exactly as it was written in the input.
+    fn raw_entries(&self, order: Order) -> Result<Entries<'_, Box<RawValue>>>;
+    /// Iterates over whole records to bundle. Files are read as they are by `entries`, but each
+    /// record of a stream is a value of its own rather than a keyed entry.
+    fn records(&self) -> Result<Entries<'_>> {
+        self.entries(Order::Listed)
+    }
+    /// Like `records`, but keeps each value exactly as it was written in the input.
+    fn raw_records(&self) -> Result<Entries<'_, Box<RawValue>>> {
+        self.raw_entries(Order::Listed)
+    }
 }
 
 pub trait JsonReader: Send + Sync {
-    fn get_object(&self) -> Result<HashMap<String, Value>>;
-    fn read_line(&self, buf: &mut String) -> Result<()>;
+    /// Accepts JSON5 and JSONC, such as comments and trailing commas, in place of strict JSON.
+    fn set_lenient(&mut self, lenient: bool);
+    /// The whole input as bytes in memory, when it can be mapped rather than streamed.
+    fn mapped(&self) -> Option<&[u8]> {
+        None
+    }
+    /// Passes each record of the input to `callback` along with the line it starts on, reading
+    /// them as `RecordReader` does. A record that failed to parse is passed on as an error, while
+    /// a failed read of the input ends the walk with an error.
+    fn for_each_record(
+        &self,
+        callback: &mut dyn FnMut(usize, serde_json::Result<Box<RawValue>>) -> Result<()>,
+    ) -> Result<()>;
+    /// Walks the top-level object or array, passing each entry or element to `callback` as soon
+    /// as it is parsed. Object values whose key is rejected by `include` are skipped without
+    /// being built.
+    fn for_each_entry(
+        &self,
+        include: &dyn Fn(&str) -> bool,
+        callback: &mut dyn FnMut(EntryKey, Value) -> Result<()>,
+    ) -> Result<()>;
+    /// Like `for_each_entry`, but passes each value on exactly as it was written in the input.
+    fn for_each_raw_entry(
+        &self,
+        include: &dyn Fn(&str) -> bool,
+        callback: &mut dyn FnMut(EntryKey, Box<RawValue>) -> Result<()>,
+    ) -> Result<()>;
 }
 
+/// A directory, a single file or stdin to read entries from, along with the argument it was
+/// given as
 #[derive(Clone)]
-pub struct JsonSourceInput(Arc<dyn JsonSource>);
+pub struct JsonSourceInput(Arc<RwLock<dyn JsonSource>>, String);
+
+impl JsonSourceInput {
+    /// The path, or `-` for stdin, that the input was given as.
+    pub fn name(&self) -> &str {
+        &self.1
+    }
+}
 
 impl std::str::FromStr for JsonSourceInput {
     type Err = Report;
 
     fn from_str(input: &str) -> Result<Self> {
-        match input {
-            "-" => Ok(JsonSourceInput(Arc::new(InputStdin::new()))),
+        let source: Arc<RwLock<dyn JsonSource>> = match input {
+            "-" => Arc::new(RwLock::new(InputStdin::new()?)),
             input => {
                 let path = PathBuf::from(input);
                 if path.is_dir() {
-                    Ok(JsonSourceInput(Arc::new(InputDirectory::new(path))))
+                    Arc::new(RwLock::new(InputDirectory::new(path)))
                 } else {
-                    Err(eyre!("Cannot read entries from file: {input}"))
+                    // A single file is read as one entry, named like a file in a directory
+                    Arc::new(RwLock::new(
+                        InputFile::new(path)
+                            .map_err(|e| eyre!("Cannot read entries from {input}: {e}"))?,
+                    ))
                 }
             }
-        }
+        };
+        Ok(JsonSourceInput(source, input.to_string()))
     }
 }
 
 impl Deref for JsonSourceInput {
-    type Target = Arc<dyn JsonSource>;
+    type Target = Arc<RwLock<dyn JsonSource>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -49,20 +185,22 @@ impl Deref for JsonSourceInput {
 }
 
 #[derive(Clone)]
-pub struct JsonReaderInput(Arc<dyn JsonReader>);
+pub struct JsonReaderInput(Arc<RwLock<dyn JsonReader>>);
 
 impl std::str::FromStr for JsonReaderInput {
     type Err = Report;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         match input {
-            "-" => Ok(JsonReaderInput(Arc::new(InputStdin::new()))),
+            "-" => Ok(JsonReaderInput(Arc::new(RwLock::new(InputStdin::new()?)))),
             input => {
                 let path = PathBuf::from(input);
                 if path.is_dir() {
                     Err(eyre!("Cannot read object from directory: {input}"))
                 } else {
-                    Ok(JsonReaderInput(Arc::new(InputFile::new(path)?)))
+                    Ok(JsonReaderInput(Arc::new(RwLock::new(InputFile::new(
+                        path,
+                    )?))))
                 }
             }
         }
@@ -70,7 +208,7 @@ impl std::str::FromStr for JsonReaderInput {
 }
 
 impl Deref for JsonReaderInput {
-    type Target = Arc<dyn JsonReader>;
+    type Target = Arc<RwLock<dyn JsonReader>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -1,30 +1,181 @@
-use super::{file::read_object, JsonSource};
+use super::{
+    file::read_json, Entries, ErrorPolicy, FileFilter, FileKey, JsonSource, KeyField, Order,
+    Recursion,
+};
+use crate::compression::strip_extension;
 use eyre::Result;
-use serde_json::Value;
-use std::path::PathBuf;
+use rayon::prelude::*;
+use serde_json::{value::RawValue, Value};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
+
+/// Number of files parsed in parallel ahead of the entry currently being consumed
+const READ_AHEAD: usize = 64;
 
 #[derive(Clone)]
 pub struct InputDirectory {
     path: PathBuf,
+    lenient: bool,
+    recursion: Option<Recursion>,
+    filter: FileFilter,
+    policy: ErrorPolicy,
+    keys: FileKey,
 }
 
 impl InputDirectory {
     pub fn new(path: PathBuf) -> Self {
-        InputDirectory { path }
+        InputDirectory {
+            path,
+            lenient: false,
+            recursion: None,
+            filter: FileFilter::default(),
+            policy: ErrorPolicy::default(),
+            keys: FileKey::default(),
+        }
+    }
+
+    fn read_entries<V: Nest + KeyField + Send + 'static>(
+        &self,
+        order: Order,
+        read: fn(&Path, bool) -> Result<V>,
+    ) -> Result<Entries<'_, V>> {
+        let ancestors = vec![std::fs::canonicalize(&self.path)?];
+        let filter = self.filter.with_ignore_file(&self.path)?;
+        let paths = sorted_paths(&self.path, order, &self.keys)?;
+        let nodes =
+            paths.flat_map(
+                move |path| match self.node(path, 1, &ancestors, &filter, order) {
+                    Ok(nodes) => nodes.into_iter().map(Ok).collect(),
+                    Err(e) => vec![Err(e)],
+                },
+            );
+        let nodes: Box<dyn Iterator<Item = Result<(String, Node)>> + Send> =
+            match (order.is_sorted(), &self.keys) {
+                // A key read from a field is only known once its file is parsed, so each file is
+                // parsed for its key first, and only the keys and paths are kept to be sorted
+                (true, FileKey::Field(_)) => {
+                    let mut keyed: Vec<(String, (String, Node))> = nodes
+                        .collect::<Result<Vec<_>>>()?
+                        .into_par_iter()
+                        .map(|(name, node)| (self.field_key(&name, &node), (name, node)))
+                        .collect();
+                    keyed.sort_by_cached_key(|(key, _)| order.key(key));
+                    Box::new(keyed.into_iter().map(|(_, node)| Ok(node)))
+                }
+                _ => Box::new(nodes),
+            };
+        Ok(Box::new(DirectoryEntries {
+            nodes,
+            reader: FileReader {
+                read,
+                lenient: self.lenient,
+                policy: self.policy.clone(),
+                keys: self.keys.clone(),
+            },
+            ready: VecDeque::with_capacity(READ_AHEAD),
+        }))
+    }
+
+    /// The key the entry `name` is sorted by when keys are read from a field. A file that cannot
+    /// be parsed, or lacks the field, is sorted by its name, and is dealt with once it is read.
+    fn field_key(&self, name: &str, node: &Node) -> String {
+        let Node::File(path) = node else {
+            return name.to_string();
+        };
+        read_json::<Value>(path, self.lenient)
+            .ok()
+            .and_then(|value| self.keys.field(&value))
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    /// Names the file at `path`, or lists the subdirectory at `path`, `depth` levels below the
+    /// input, when recursion allows it and `filter` includes it. A flattened subdirectory becomes
+    /// all of the files below it, named by their path.
+    fn node(
+        &self,
+        path: PathBuf,
+        depth: usize,
+        ancestors: &[PathBuf],
+        filter: &FileFilter,
+        order: Order,
+    ) -> Result<Vec<(String, Node)>> {
+        let is_dir = path.is_dir();
+        let relative = path.strip_prefix(&self.path).unwrap_or(&path);
+        if !filter.includes(relative, is_dir) {
+            log::info!("Skipping {}, it is filtered out", path.display());
+            return Ok(vec![]);
+        }
+        if !is_dir {
+            return Ok(vec![(self.keys.name(&path), Node::File(path))]);
+        }
+        let Some(recursion) = self.recursion else {
+            log::warn!("Skipping subdirectory {}", path.display());
+            return Ok(vec![]);
+        };
+        if recursion
+            .max_depth
+            .is_some_and(|max_depth| depth > max_depth)
+        {
+            log::warn!(
+                "Skipping {}, it is deeper than the depth limit",
+                path.display()
+            );
+            return Ok(vec![]);
+        }
+        let canonical = std::fs::canonicalize(&path)?;
+        if ancestors.contains(&canonical) {
+            log::warn!(
+                "Skipping {}, it links back to {}",
+                path.display(),
+                canonical.display()
+            );
+            return Ok(vec![]);
+        }
+        let ancestors = [ancestors, &[canonical]].concat();
+        let mut children = Vec::new();
+        for child in sorted_paths(&path, order, &self.keys)? {
+            children.extend(self.node(child, depth + 1, &ancestors, filter, order)?);
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Ok(match recursion.flatten {
+            true => children
+                .into_iter()
+                .map(|(child, node)| (format!("{name}/{child}"), node))
+                .collect(),
+            false => vec![(name.to_string(), Node::Directory(children))],
+        })
     }
 }
 
 impl JsonSource for InputDirectory {
-    fn get_entries(&self, sort: bool) -> Vec<(String, Value)> {
-        read_entries(&self.path, sort).expect("Error reading entries from directory")
+    fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    fn set_recursion(&mut self, recursion: Option<Recursion>) {
+        self.recursion = recursion;
+    }
+
+    fn set_file_filter(&mut self, filter: FileFilter) {
+        self.filter = filter;
+    }
+
+    fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.policy = policy;
+    }
+
+    fn set_file_key(&mut self, keys: FileKey) {
+        self.keys = keys;
     }
 
-    fn read_entry(&self) -> Result<(String, Value)> {
-        let file = &self.path;
-        log::info!("Reading file {}", &file.display());
-        let object = read_object(file)?;
-        let name = file.file_stem().unwrap().to_str().unwrap();
-        Ok((name.to_string(), object))
+    fn entries(&self, order: Order) -> Result<Entries<'_>> {
+        self.read_entries(order, read_json)
+    }
+
+    fn raw_entries(&self, order: Order) -> Result<Entries<'_, Box<RawValue>>> {
+        self.read_entries(order, read_json)
     }
 }
 
@@ -42,23 +193,148 @@ impl std::str::FromStr for InputDirectory {
     }
 }
 
-pub fn read_entries(dir: &PathBuf, sort: bool) -> Result<Vec<(String, Value)>> {
-    let mut entries: Vec<(String, Value)> = Vec::new();
-    let dir_path = PathBuf::from(dir);
-    let dir_entries = std::fs::read_dir(dir_path)?;
-    for entry in dir_entries {
-        if let Ok(entry) = entry {
-            let file_path = entry.path();
-            let object = read_object(&file_path)?;
-            let name = file_path.file_stem().unwrap().to_str().unwrap();
-            log::info!("Appending entry {} from {:?}", name, file_path);
-            entries.push((name.to_string(), object));
+/// Lists the paths in `dir`, put in `order` by their name when it sorts and lazily otherwise.
+fn sorted_paths(
+    dir: &Path,
+    order: Order,
+    keys: &FileKey,
+) -> Result<Box<dyn Iterator<Item = PathBuf> + Send>> {
+    let paths = std::fs::read_dir(dir)?.flatten().map(|entry| entry.path());
+    Ok(if order.is_sorted() {
+        // Only the file names are sorted up front, the contents are still read lazily
+        let mut paths: Vec<PathBuf> = paths.collect();
+        paths.sort_by_cached_key(|path| order.key(&keys.name(path)));
+        Box::new(paths.into_iter())
+    } else {
+        Box::new(paths)
+    })
+}
+
+/// A file to read as one entry, or a subdirectory to read as an object of entries
+enum Node {
+    File(PathBuf),
+    Directory(Vec<(String, Node)>),
+}
+
+impl Node {
+    /// Reads the file, or every file below the subdirectory, as the entry `name`. Any file that
+    /// `reader` could not read but whose error policy lets the read carry on is left out.
+    fn read<V: Nest + KeyField + Send>(
+        self,
+        name: String,
+        reader: &FileReader<V>,
+    ) -> Result<Option<(String, V)>> {
+        match self {
+            Node::File(path) => reader.read(name, &path),
+            Node::Directory(children) => {
+                let children = children
+                    .into_par_iter()
+                    .map(|(name, node)| node.read(name, reader))
+                    .collect::<Result<Vec<_>>>()?;
+                let object = V::nest(children.into_iter().flatten().collect())?;
+                Ok(Some((name, object)))
+            }
+        }
+    }
+}
+
+/// Parses the files of a directory input, handing those that fail to `policy`
+struct FileReader<V> {
+    read: fn(&Path, bool) -> Result<V>,
+    lenient: bool,
+    policy: ErrorPolicy,
+    keys: FileKey,
+}
+
+impl<V: KeyField> FileReader<V> {
+    /// Parses the file at `path` as the entry `name`, unless its key is read from a field, or
+    /// returns `None` if it failed and the policy skips it.
+    fn read(&self, name: String, path: &Path) -> Result<Option<(String, V)>> {
+        match (self.read)(path, self.lenient) {
+            Ok(value) => Ok(Some(match self.keys {
+                FileKey::Field(_) => (self.keys.key(path, &value), value),
+                _ => (name, value),
+            })),
+            Err(e) => {
+                self.policy.handle(path, e)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Values that the entries of a subdirectory can be gathered into as one nested object
+trait Nest: Sized {
+    fn nest(entries: Vec<(String, Self)>) -> Result<Self>;
+}
+
+impl Nest for Value {
+    fn nest(entries: Vec<(String, Self)>) -> Result<Self> {
+        Ok(Value::Object(entries.into_iter().collect()))
+    }
+}
+
+impl Nest for Box<RawValue> {
+    /// Writes the object around the entries by hand, so the values are still copied verbatim.
+    fn nest(entries: Vec<(String, Self)>) -> Result<Self> {
+        let mut object = String::from("{");
+        for (i, (key, value)) in entries.iter().enumerate() {
+            if i > 0 {
+                object.push(',');
+            }
+            object.push_str(&serde_json::to_string(key)?);
+            object.push(':');
+            object.push_str(value.get());
         }
+        object.push('}');
+        Ok(RawValue::from_string(object)?)
     }
+}
+
+/// Lazily reads the files of a directory, parsing up to `READ_AHEAD` of them in parallel
+/// whenever the previously parsed entries have been consumed.
+struct DirectoryEntries<'a, V> {
+    nodes: Box<dyn Iterator<Item = Result<(String, Node)>> + Send + 'a>,
+    reader: FileReader<V>,
+    ready: VecDeque<Result<(String, V)>>,
+}
 
-    if sort {
-        entries.sort_by(|a, b| a.0.cmp(&b.0));
+impl<V: Nest + KeyField + Send> Iterator for DirectoryEntries<'_, V> {
+    type Item = Result<(String, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Skipped files leave gaps, so keep reading until an entry is ready or the files run out
+        while self.ready.is_empty() {
+            let chunk: Vec<Result<(String, Node)>> = self.nodes.by_ref().take(READ_AHEAD).collect();
+            if chunk.is_empty() {
+                return None;
+            }
+            let reader = &self.reader;
+            self.ready = chunk
+                .into_par_iter()
+                .filter_map(|node| {
+                    let (name, node) = match node {
+                        Ok(node) => node,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    let entry = node.read(name, reader).transpose()?;
+                    if let Ok((name, _)) = &entry {
+                        log::info!("Appending entry {}", name);
+                    }
+                    Some(entry)
+                })
+                .collect::<Vec<_>>()
+                .into();
+        }
+        self.ready.pop_front()
     }
+}
 
-    Ok(entries)
+/// The file name without its extension, or both extensions for a compressed file like `a.json.gz`.
+pub fn entry_name(path: &Path) -> String {
+    strip_extension(path)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
 }
@@ -1,53 +1,266 @@
-use super::JsonReader;
-use eyre::{eyre, Result};
-use serde_json::Value;
+use super::{
+    Entries, EntryKey, ErrorPolicy, FileKey, JsonReader, JsonSource, KeyField, Order, RecordReader,
+};
+use crate::compression::{decompress, Compression};
+use eyre::{eyre, Report, Result};
+use memmap2::Mmap;
+use serde::de::{DeserializeOwned, Deserializer, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::{value::RawValue, Value};
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    fmt,
     fs::File,
-    io::{BufRead, BufReader},
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
-#[derive(Clone)]
 pub struct InputFile {
     path: PathBuf,
-    reader: Arc<Mutex<BufReader<File>>>,
+    /// The file mapped into memory, once it has been asked for
+    mmap: OnceLock<Option<Mmap>>,
+    lenient: bool,
+    policy: ErrorPolicy,
+    keys: FileKey,
 }
 
 impl InputFile {
     pub fn new(path: PathBuf) -> Result<Self> {
-        let file = File::open(&path)?;
-        let reader = BufReader::new(file);
+        // Opened here only so a missing or unreadable file is reported up front
+        File::open(&path)?;
         Ok(Self {
             path,
-            reader: Arc::new(Mutex::new(reader)),
+            mmap: OnceLock::new(),
+            lenient: false,
+            policy: ErrorPolicy::default(),
+            keys: FileKey::default(),
         })
     }
+
+    fn each_entry<V: DeserializeOwned>(
+        &self,
+        include: &dyn Fn(&str) -> bool,
+        callback: &mut dyn FnMut(EntryKey, V) -> Result<()>,
+    ) -> Result<()> {
+        if !self.lenient {
+            return stream_entries(open(&self.path)?, include, callback);
+        }
+        // Lenient input is checked as a whole before any entry is handed out
+        let mut content = String::new();
+        open(&self.path)?.read_to_string(&mut content)?;
+        let content = strict_json(&content, true, &self.path.display().to_string())?;
+        stream_entries(content.as_bytes(), include, callback)
+    }
+
+    /// The whole file as a single entry, named like a file in an `InputDirectory`, or none if it
+    /// could not be read and the error policy leaves it out.
+    fn file_entry<V: DeserializeOwned + KeyField + 'static>(&self) -> Entries<'_, V> {
+        Box::new(
+            std::iter::once_with(|| {
+                log::info!("Reading file {}", self.path.display());
+                match read_json(&self.path, self.lenient) {
+                    Ok(value) => Ok(Some((self.keys.key(&self.path, &value), value))),
+                    Err(e) => self.policy.handle(&self.path, e).map(|()| None),
+                }
+            })
+            .filter_map(Result::transpose),
+        )
+    }
+}
+
+/// Memory-maps non-empty regular files, leaving compressed files, pipes, devices and anything that
+/// fails to map to be read through a buffered reader instead.
+fn map_file(path: &Path) -> Option<Mmap> {
+    let mut file = BufReader::new(File::open(path).ok()?);
+    // Compressed files can only be decoded as a stream
+    if Compression::detect(&mut file, Some(path)).ok()?.is_some() {
+        return None;
+    }
+    let file = file.get_ref();
+    let metadata = file.metadata().ok()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return None;
+    }
+    // SAFETY: the map is only ever read, and like any tool reading its input we assume the file
+    // is not truncated or rewritten by another process while the command runs.
+    match unsafe { Mmap::map(file) } {
+        Ok(mmap) => Some(mmap),
+        Err(e) => {
+            log::debug!("Falling back to buffered reads: {e}");
+            None
+        }
+    }
 }
 
 impl JsonReader for InputFile {
-    fn get_object(&self) -> Result<HashMap<String, Value>> {
-        read_hashmap(&self.path)
+    fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
     }
 
-    fn read_line(&self, buf: &mut String) -> Result<()> {
-        let mut reader = self.reader.lock().map_err(|e| eyre!("{e}"))?;
-        reader.read_line(buf)?;
-        Ok(())
+    /// Maps the file on first use, unless it is compressed or cannot be mapped.
+    fn mapped(&self) -> Option<&[u8]> {
+        self.mmap.get_or_init(|| map_file(&self.path)).as_deref()
+    }
+
+    /// Reads a memory-mapped file straight from the map instead of through a reader.
+    fn for_each_record(
+        &self,
+        callback: &mut dyn FnMut(usize, serde_json::Result<Box<RawValue>>) -> Result<()>,
+    ) -> Result<()> {
+        match self.mapped() {
+            Some(mut bytes) => each_record(&mut bytes, callback),
+            None => each_record(&mut *open(&self.path)?, callback),
+        }
+    }
+
+    fn for_each_entry(
+        &self,
+        include: &dyn Fn(&str) -> bool,
+        callback: &mut dyn FnMut(EntryKey, Value) -> Result<()>,
+    ) -> Result<()> {
+        self.each_entry(include, callback)
+    }
+
+    fn for_each_raw_entry(
+        &self,
+        include: &dyn Fn(&str) -> bool,
+        callback: &mut dyn FnMut(EntryKey, Box<RawValue>) -> Result<()>,
+    ) -> Result<()> {
+        self.each_entry(include, callback)
     }
 }
 
-pub fn read_object(input: &PathBuf) -> Result<Value> {
-    let file = File::open(input)?;
-    let reader = BufReader::new(file);
-    let json_value = serde_json::from_reader(reader)?;
-    Ok(json_value)
+impl JsonSource for InputFile {
+    fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    fn set_file_key(&mut self, keys: FileKey) {
+        self.keys = keys;
+    }
+
+    fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.policy = policy;
+    }
+
+    fn entries(&self, _order: Order) -> Result<Entries<'_>> {
+        Ok(self.file_entry())
+    }
+
+    fn raw_entries(&self, _order: Order) -> Result<Entries<'_, Box<RawValue>>> {
+        Ok(self.file_entry())
+    }
 }
 
-pub fn read_hashmap(input: &PathBuf) -> Result<HashMap<String, Value>> {
+/// Reads every record of `input` with a `RecordReader`, passing each one to `callback`.
+pub fn each_record<R: BufRead + ?Sized>(
+    input: &mut R,
+    callback: &mut dyn FnMut(usize, serde_json::Result<Box<RawValue>>) -> Result<()>,
+) -> Result<()> {
+    let mut records = RecordReader::default();
+    while let Some((line, record)) = records.next_record(input) {
+        match record {
+            Err(e) if e.is_io() => return Err(eyre!("Error reading input: {e}")),
+            record => callback(line, record)?,
+        }
+    }
+    Ok(())
+}
+
+/// Opens `input` for buffered reading, decompressing it on the fly if it is compressed.
+pub fn open(input: &Path) -> Result<Box<dyn BufRead + Send>> {
     let file = File::open(input)?;
-    let reader = BufReader::new(file);
-    let hashmap: HashMap<String, Value> = serde_json::from_reader(reader)?;
-    Ok(hashmap)
+    Ok(decompress(BufReader::new(file), Some(input))?)
+}
+
+/// Reads the single JSON value in `input`. A `RawValue` is read without re-encoding it, only
+/// dropping the whitespace around it, and objects keep their keys in document order.
+pub fn read_json<V: DeserializeOwned>(input: &Path, lenient: bool) -> Result<V> {
+    let mut content = String::new();
+    open(input)?.read_to_string(&mut content)?;
+    let content = strict_json(&content, lenient, &input.display().to_string())?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Returns `content` unchanged if it is strict JSON. Otherwise, when `lenient`, it is parsed as
+/// JSON5 (which covers JSONC) and re-encoded as strict JSON, with a warning naming `source`.
+pub fn strict_json<'a>(content: &'a str, lenient: bool, source: &str) -> Result<Cow<'a, str>> {
+    if !lenient || serde_json::from_str::<IgnoredAny>(content).is_ok() {
+        return Ok(Cow::Borrowed(content));
+    }
+    let value: Value =
+        json5::from_str(content).map_err(|e| eyre!("Error parsing {source} as JSON5: {e}"))?;
+    log::warn!("Parsed {source} leniently, it is not strict JSON");
+    Ok(Cow::Owned(serde_json::to_string(&value)?))
+}
+
+/// Parses a single top-level object or array from `reader` one entry at a time, so only the value
+/// currently being handed to `callback` is held in memory.
+pub fn stream_entries<R: Read, V: DeserializeOwned>(
+    reader: R,
+    include: &dyn Fn(&str) -> bool,
+    callback: &mut dyn FnMut(EntryKey, V) -> Result<()>,
+) -> Result<()> {
+    let mut error = None;
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let visitor = EntryVisitor {
+        include,
+        callback,
+        error: &mut error,
+    };
+    let parsed = (&mut deserializer).deserialize_any(visitor);
+    // An error raised by the callback takes precedence over the parse error it was wrapped in
+    if let Some(error) = error {
+        return Err(error);
+    }
+    parsed?;
+    deserializer.end()?;
+    Ok(())
+}
+
+struct EntryVisitor<'a, V> {
+    include: &'a dyn Fn(&str) -> bool,
+    callback: &'a mut dyn FnMut(EntryKey, V) -> Result<()>,
+    error: &'a mut Option<Report>,
+}
+
+impl<V> EntryVisitor<'_, V> {
+    /// Passes an entry to the callback, keeping its error to be returned in place of the serde
+    /// error that stops the parse.
+    fn forward<E: Error>(&mut self, key: EntryKey, value: V) -> Result<(), E> {
+        (self.callback)(key, value).map_err(|e| {
+            let message = e.to_string();
+            *self.error = Some(e);
+            E::custom(message)
+        })
+    }
+}
+
+impl<'de, V: DeserializeOwned> Visitor<'de> for EntryVisitor<'_, V> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON object or array")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if !(self.include)(&key) {
+                map.next_value::<IgnoredAny>()?;
+                continue;
+            }
+            let value = map.next_value::<V>()?;
+            self.forward(EntryKey::Key(key), value)?;
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        let mut i = 0;
+        while let Some(value) = seq.next_element::<V>()? {
+            self.forward(EntryKey::Index(i), value)?;
+            i += 1;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,242 @@
+use crate::compression::strip_extension;
+use eyre::{eyre, Result};
+use regex::Regex;
+use std::path::Path;
+
+/// Files in an input directory listing patterns to leave out, one per line
+pub const IGNORE_FILE: &str = ".jsrmxignore";
+
+/// The files read when no include pattern is given
+const DEFAULT_INCLUDE: &str = "*.json";
+
+/// A shell-style pattern. `*` and `?` match within one path component, `**` matches across
+/// them, `[abc]` and `[!abc]` match a character class and `{a,b}` matches either alternative.
+#[derive(Clone, Debug)]
+pub struct Glob {
+    regex: Regex,
+    /// Patterns holding a `/` are matched against the path below the input directory, all others
+    /// against the file name alone
+    path: bool,
+    /// Patterns ending in `/` only match directories
+    dir_only: bool,
+}
+
+impl Glob {
+    pub fn new(glob: &str) -> Result<Self> {
+        let dir_only = glob.ends_with('/');
+        let trimmed = glob.trim_end_matches('/');
+        let path = trimmed.contains('/');
+        let regex = Regex::new(&to_regex(trimmed.trim_start_matches('/'))?)
+            .map_err(|e| eyre!("Invalid glob {glob}: {e}"))?;
+        Ok(Self {
+            regex,
+            path,
+            dir_only,
+        })
+    }
+
+    /// Whether the file or directory at `relative`, a path below the input directory, matches.
+    pub fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let text = match self.path {
+            true => relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            false => relative
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        };
+        self.regex.is_match(&text)
+    }
+}
+
+impl std::str::FromStr for Glob {
+    type Err = String;
+
+    fn from_str(glob: &str) -> Result<Self, Self::Err> {
+        Glob::new(glob).map_err(|e| e.to_string())
+    }
+}
+
+/// Chooses the files a directory input reads. A file is read when it matches an include pattern,
+/// either by its own name or by its name without a compression extension, and no exclude pattern.
+/// Subdirectories are only checked against the exclude patterns.
+#[derive(Clone, Debug)]
+pub struct FileFilter {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        Self {
+            include: vec![Glob::new(DEFAULT_INCLUDE).expect("Default include glob is valid")],
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl FileFilter {
+    /// Reads the files matching `include`, or `*.json` when it is empty, except for any matching
+    /// `exclude`.
+    pub fn new(include: Vec<Glob>, exclude: Vec<Glob>) -> Self {
+        match include.is_empty() {
+            true => Self {
+                exclude,
+                ..Self::default()
+            },
+            false => Self { include, exclude },
+        }
+    }
+
+    /// Adds the patterns listed in the `.jsrmxignore` file of `dir`, if it has one, to the
+    /// exclude patterns. Blank lines and lines starting with `#` are ignored.
+    pub fn with_ignore_file(&self, dir: &Path) -> Result<Self> {
+        let path = dir.join(IGNORE_FILE);
+        if !path.is_file() {
+            return Ok(self.clone());
+        }
+        log::debug!("Reading ignore patterns from {}", path.display());
+        let mut filter = self.clone();
+        for line in std::fs::read_to_string(&path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            filter
+                .exclude
+                .push(Glob::new(line).map_err(|e| eyre!("Error reading {}: {e}", path.display()))?);
+        }
+        Ok(filter)
+    }
+
+    /// Whether the file or directory at `relative`, a path below the input directory, is read.
+    /// Ignore files are never read as input, whatever the globs.
+    pub fn includes(&self, relative: &Path, is_dir: bool) -> bool {
+        if !is_dir && relative.file_name().is_some_and(|name| name == IGNORE_FILE) {
+            return false;
+        }
+        if self
+            .exclude
+            .iter()
+            .any(|glob| glob.matches(relative, is_dir))
+        {
+            return false;
+        }
+        is_dir
+            || self.include.iter().any(|glob| {
+                glob.matches(relative, false) || glob.matches(&strip_compression(relative), false)
+            })
+    }
+}
+
+/// `relative` with the compression extension of its file name removed, so `a/b.json.gz` is
+/// matched like `a/b.json`.
+fn strip_compression(relative: &Path) -> std::path::PathBuf {
+    relative.with_file_name(strip_extension(relative))
+}
+
+/// Translates a glob into an anchored regular expression.
+fn to_regex(glob: &str) -> Result<String> {
+    let mut regex = String::from("^");
+    let mut alternatives = 0;
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // `**/` also matches no directories at all, so `**/a.json` matches `a.json`
+                match chars.next_if_eq(&'/') {
+                    Some(_) => regex.push_str("(?:.*/)?"),
+                    None => regex.push_str(".*"),
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    regex.push('^');
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('-') => regex.push('-'),
+                        Some(c) => regex.push_str(&regex::escape(&c.to_string())),
+                        None => return Err(eyre!("Invalid glob {glob}: unclosed [")),
+                    }
+                }
+                regex.push(']');
+            }
+            '{' => {
+                alternatives += 1;
+                regex.push_str("(?:");
+            }
+            ',' if alternatives > 0 => regex.push('|'),
+            '}' if alternatives > 0 => {
+                alternatives -= 1;
+                regex.push(')');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if alternatives > 0 {
+        return Err(eyre!("Invalid glob {glob}: unclosed {{"));
+    }
+    regex.push('$');
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> FileFilter {
+        let globs = |globs: &[&str]| globs.iter().map(|glob| Glob::new(glob).unwrap()).collect();
+        FileFilter::new(globs(include), globs(exclude))
+    }
+
+    #[test]
+    fn test_default_filter_reads_json_files() {
+        let filter = FileFilter::default();
+        assert!(filter.includes(Path::new("a.json"), false));
+        assert!(filter.includes(Path::new("a.json.gz"), false));
+        assert!(filter.includes(Path::new("sub"), true));
+        assert!(!filter.includes(Path::new("README.md"), false));
+        assert!(!filter.includes(Path::new(".gitkeep"), false));
+    }
+
+    #[test]
+    fn test_include_and_exclude_globs() {
+        let filter = filter(&["*.{json,json5}", "data/**/*.txt"], &["draft-*", "tmp/"]);
+        assert!(filter.includes(Path::new("a.json5"), false));
+        assert!(filter.includes(Path::new("data/x/y/z.txt"), false));
+        assert!(filter.includes(Path::new("data/z.txt"), false));
+        assert!(!filter.includes(Path::new("z.txt"), false));
+        assert!(!filter.includes(Path::new("sub/draft-1.json"), false));
+        assert!(!filter.includes(Path::new("tmp"), true));
+        assert!(filter.includes(Path::new("tmp.json"), false));
+    }
+
+    #[test]
+    fn test_ignore_files_never_included() {
+        let filter = filter(&["*", "**/.*"], &[]);
+        assert!(filter.includes(Path::new(".hidden"), false));
+        assert!(!filter.includes(Path::new(".jsrmxignore"), false));
+        assert!(!filter.includes(Path::new("sub/.jsrmxignore"), false));
+    }
+
+    #[test]
+    fn test_glob_classes() {
+        let glob = Glob::new("[!a-c]?.json").unwrap();
+        assert!(glob.matches(Path::new("d1.json"), false));
+        assert!(!glob.matches(Path::new("b1.json"), false));
+        assert!(Glob::new("[abc").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use super::directory::entry_name;
+use regex::Regex;
+use serde_json::{value::RawValue, Value};
+use std::path::Path;
+
+/// How the key of each file read as an entry is derived
+#[derive(Clone, Debug, Default)]
+pub enum FileKey {
+    /// The file name without its extension, or without both extensions of a compressed file
+    #[default]
+    Stem,
+    /// The file name with this suffix removed, when it ends with it
+    Trim(String),
+    /// The whole file name, extensions included
+    FullName,
+    /// The group named `key` of this regex matched against the file name, or its first group
+    Capture(Regex),
+    /// The string or number at this field path of the file's JSON, as in `metadata.name`
+    Field(String),
+}
+
+impl FileKey {
+    /// The key of the file at `path` as far as its name tells, which is every key but a `Field`.
+    /// Names a regex does not match keep their stem.
+    pub fn name(&self, path: &Path) -> String {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        match self {
+            FileKey::Stem | FileKey::Field(_) => entry_name(path),
+            FileKey::Trim(suffix) => file_name
+                .strip_suffix(suffix.as_str())
+                .unwrap_or(&file_name)
+                .to_string(),
+            FileKey::FullName => file_name.to_string(),
+            FileKey::Capture(regex) => {
+                let captured = regex
+                    .captures(&file_name)
+                    .and_then(|captures| captures.name("key").or_else(|| captures.get(1)));
+                match captured {
+                    Some(key) => key.as_str().to_string(),
+                    None => {
+                        log::warn!("{file_name} does not match {regex}, keeping its stem as key");
+                        entry_name(path)
+                    }
+                }
+            }
+        }
+    }
+
+    /// The key of the file at `path` holding `value`. A file without the key field keeps its
+    /// stem as key.
+    pub fn key<V: KeyField>(&self, path: &Path, value: &V) -> String {
+        let FileKey::Field(field) = self else {
+            return self.name(path);
+        };
+        match self.field(value) {
+            Some(key) => key,
+            None => {
+                log::warn!(
+                    "{} has no string or number field {field}, keeping its stem as key",
+                    path.display()
+                );
+                entry_name(path)
+            }
+        }
+    }
+
+    /// The key `value` holds, when keys are read from a field and it has a string or number there.
+    pub fn field<V: KeyField>(&self, value: &V) -> Option<String> {
+        let FileKey::Field(field) = self else {
+            return None;
+        };
+        value.key_field(&format!("/{}", field.replace('.', "/")))
+    }
+}
+
+/// Values that a key can be read from
+pub trait KeyField {
+    /// The string or number at the JSON `pointer`, if there is one.
+    fn key_field(&self, pointer: &str) -> Option<String>;
+}
+
+impl KeyField for Value {
+    fn key_field(&self, pointer: &str) -> Option<String> {
+        match self.pointer(pointer)? {
+            Value::String(key) => Some(key.clone()),
+            Value::Number(key) => Some(key.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl KeyField for Box<RawValue> {
+    /// Parses the value to find the field, without changing the raw text that is written.
+    fn key_field(&self, pointer: &str) -> Option<String> {
+        serde_json::from_str::<Value>(self.get())
+            .ok()?
+            .key_field(pointer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_keys_from_file_names() {
+        let path = Path::new("dir/app.settings.json.gz");
+        assert_eq!(FileKey::Stem.name(path), "app.settings");
+        assert_eq!(
+            FileKey::Trim(".settings.json.gz".to_string()).name(path),
+            "app"
+        );
+        assert_eq!(
+            FileKey::Trim(".yaml".to_string()).name(path),
+            "app.settings.json.gz"
+        );
+        assert_eq!(FileKey::FullName.name(path), "app.settings.json.gz");
+        let capture = Regex::new(r"^(\w+)\.").unwrap();
+        assert_eq!(FileKey::Capture(capture).name(path), "app");
+        let named = Regex::new(r"^\w+\.(?<key>\w+)").unwrap();
+        assert_eq!(FileKey::Capture(named).name(path), "settings");
+    }
+
+    #[test]
+    fn test_keys_from_fields() {
+        let path = Path::new("a.json");
+        let key = FileKey::Field("meta.id".to_string());
+        assert_eq!(key.key(path, &json!({"meta": {"id": 7}})), "7");
+        assert_eq!(key.key(path, &json!({"meta": {"id": [7]}})), "a");
+        let raw = RawValue::from_string(r#"{"meta": {"id": "x"}}"#.to_string()).unwrap();
+        assert_eq!(key.key(path, &raw), "x");
+    }
+}
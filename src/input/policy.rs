@@ -0,0 +1,128 @@
+use eyre::{eyre, Report, Result};
+use std::{
+    fs::{copy, create_dir_all},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// What a directory input does with a file it cannot read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop reading and return the error
+    #[default]
+    Fail,
+    /// Leave the file out and keep reading
+    Skip,
+    /// Copy the file into a quarantine directory, then leave it out and keep reading
+    Quarantine,
+}
+
+impl std::str::FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            "quarantine" => Ok(Self::Quarantine),
+            s => Err(format!(
+                "Unknown error policy {s}, expected fail, skip or quarantine"
+            )),
+        }
+    }
+}
+
+/// Applies an `OnError` mode to the files that could not be read, remembering each of them and
+/// why so they can be summarized once every input has been read. Clones share the same record.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPolicy {
+    on_error: OnError,
+    quarantine: Option<PathBuf>,
+    failures: Arc<Mutex<Vec<(PathBuf, String)>>>,
+}
+
+impl ErrorPolicy {
+    /// With `OnError::Quarantine`, bad files are copied below `quarantine`, keeping the path they
+    /// were read from, made relative.
+    pub fn new(on_error: OnError, quarantine: Option<PathBuf>) -> Result<Self> {
+        if on_error == OnError::Quarantine && quarantine.is_none() {
+            return Err(eyre!("Quarantining files needs a quarantine directory"));
+        }
+        Ok(Self {
+            on_error,
+            quarantine,
+            failures: Arc::default(),
+        })
+    }
+
+    /// Handles the failure to read the file at `path`. Returns the error when failing, or `Ok`
+    /// when the file is to be left out.
+    pub fn handle(&self, path: &Path, error: Report) -> Result<()> {
+        let reason = error.to_string();
+        self.failures
+            .lock()
+            .map_err(|e| eyre!("{e}"))?
+            .push((path.to_path_buf(), reason.clone()));
+        match (self.on_error, &self.quarantine) {
+            (OnError::Fail, _) | (OnError::Quarantine, None) => {
+                Err(eyre!("Error reading {}: {reason}", path.display()))
+            }
+            (OnError::Skip, _) => {
+                log::warn!("Skipping {}: {reason}", path.display());
+                Ok(())
+            }
+            (OnError::Quarantine, Some(quarantine)) => {
+                // Files of different inputs keep apart, even when they share a name
+                let target = quarantine.join(relative(path));
+                if let Some(parent) = target.parent() {
+                    create_dir_all(parent)?;
+                }
+                copy(path, &target).map_err(|e| {
+                    eyre!(
+                        "Error quarantining {} to {}: {e}",
+                        path.display(),
+                        target.display()
+                    )
+                })?;
+                log::warn!(
+                    "Quarantined {} to {}: {reason}",
+                    path.display(),
+                    target.display()
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// The files that could not be read so far, along with why.
+    pub fn failures(&self) -> Vec<(PathBuf, String)> {
+        self.failures
+            .lock()
+            .map(|failures| failures.clone())
+            .unwrap_or_default()
+    }
+
+    /// Logs every file that could not be read and why, if there were any.
+    pub fn summarize(&self) {
+        let failures = self.failures();
+        if failures.is_empty() {
+            return;
+        }
+        log::warn!("{} file(s) could not be read:", failures.len());
+        for (path, reason) in failures {
+            log::warn!("  {}: {reason}", path.display());
+        }
+    }
+}
+
+/// `path` without its root, and with `..` components replaced by `__`, so that it stays inside
+/// whatever directory it is joined to.
+fn relative(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            Component::ParentDir => Some("__".as_ref()),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
@@ -0,0 +1,285 @@
+use serde::Deserialize;
+use serde_json::{value::RawValue, Deserializer};
+use std::io::{BufRead, Read};
+
+/// Prefixes each record of an RFC 7464 JSON text sequence
+pub const RECORD_SEPARATOR: char = '\u{1e}';
+
+/// Reads a stream of JSON texts one record at a time with serde_json's deserializer. Records may
+/// be one per line, pretty-printed over several lines, back to back as in `{}{}`, or prefixed by
+/// RFC 7464 record separators, in any mix.
+///
+/// Input is read a line at a time, and a record that continues past its line is read on from the
+/// input as a stream. A record that fails to parse is returned as an error, and reading resumes
+/// at the next line after the record's first one that starts with a value. Pretty-printed records
+/// indent everything but their first line, so a broken one is skipped whole, while a record cut
+/// off at the end of its line does not take the records it ran into down with it.
+#[derive(Debug, Default)]
+pub struct RecordReader {
+    /// Input that has been read but not yet used, always ending where the input continues
+    buffer: Vec<u8>,
+    /// Where the unused part of `buffer` starts
+    pos: usize,
+    /// The number of the line the unused part of `buffer` starts on
+    line: usize,
+}
+
+impl RecordReader {
+    /// Reads the next record from `input`, returning the line it starts on and the record, or
+    /// `None` at the end of input. A failed read of `input` is returned as an I/O error, which
+    /// `serde_json::Error::is_io` tells apart from a record that failed to parse.
+    pub fn next_record<R: BufRead + ?Sized>(
+        &mut self,
+        input: &mut R,
+    ) -> Option<(usize, serde_json::Result<Box<RawValue>>)> {
+        loop {
+            while let Some(&byte) = self.buffer.get(self.pos) {
+                if !is_separator(byte) {
+                    break;
+                }
+                self.pos += 1;
+                if byte == b'\n' {
+                    self.line += 1;
+                }
+            }
+            if self.pos < self.buffer.len() {
+                break;
+            }
+            match self.read_line(input) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some((self.line, Err(serde_json::Error::io(e)))),
+            }
+        }
+        let start = self.line;
+        let parsed =
+            <&RawValue>::deserialize(&mut Deserializer::from_slice(&self.buffer[self.pos..]))
+                .map(ToOwned::to_owned);
+        let result = match parsed {
+            // The record goes on past the line, so the rest of it is streamed from the input
+            Err(e) if e.is_eof() => self.read_on(input),
+            parsed => parsed,
+        };
+        match result {
+            Ok(record) => {
+                let text = record.get().as_bytes();
+                self.line += count_lines(text);
+                self.pos += text.len();
+                Some((start, Ok(record)))
+            }
+            Err(e) if e.is_io() => Some((start, Err(e))),
+            Err(e) => match self.resume(input) {
+                Ok(()) => Some((start, Err(e))),
+                Err(e) => Some((start, Err(serde_json::Error::io(e)))),
+            },
+        }
+    }
+
+    /// Reads the next line of `input` that is not blank, returning the line number and its text
+    /// without the line ending, or `None` at the end of input.
+    pub fn next_line<R: BufRead + ?Sized>(
+        &mut self,
+        input: &mut R,
+    ) -> Option<(usize, std::io::Result<Vec<u8>>)> {
+        loop {
+            if self.pos == self.buffer.len() {
+                match self.read_line(input) {
+                    Ok(0) => return None,
+                    Ok(_) => {}
+                    Err(e) => return Some((self.line, Err(e))),
+                }
+            }
+            let rest = &self.buffer[self.pos..];
+            let end = rest
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(rest.len(), |n| n + 1);
+            let line = rest[..end].trim_ascii().to_vec();
+            let number = self.line;
+            self.pos += end;
+            self.line += count_lines(&rest[..end]);
+            if !line.is_empty() {
+                return Some((number, Ok(line)));
+            }
+        }
+    }
+
+    /// Input that has been read but not yet used.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer[self.pos..]
+    }
+
+    /// Replaces the used part of the buffer with the next line of `input`, returning its length.
+    fn read_line<R: BufRead + ?Sized>(&mut self, input: &mut R) -> std::io::Result<usize> {
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+        input.read_until(b'\n', &mut self.buffer)
+    }
+
+    /// Parses the record starting at `pos` from the rest of the buffer and then from `input`,
+    /// keeping everything it reads in the buffer.
+    fn read_on<R: BufRead + ?Sized>(&mut self, input: &mut R) -> serde_json::Result<Box<RawValue>> {
+        let buffered = std::mem::take(&mut self.buffer);
+        let mut source = Recorded {
+            input,
+            read: Vec::new(),
+        };
+        let result = Box::<RawValue>::deserialize(&mut Deserializer::from_reader(
+            (&buffered[self.pos..]).chain(&mut source),
+        ));
+        self.buffer = buffered;
+        self.buffer.extend_from_slice(&source.read);
+        result
+    }
+
+    /// Moves past a record that failed to parse, to the next line after its first one that
+    /// starts with a value.
+    fn resume<R: BufRead + ?Sized>(&mut self, input: &mut R) -> std::io::Result<()> {
+        loop {
+            self.skip_line(input)?;
+            if self.pos == self.buffer.len() && self.read_line(input)? == 0 {
+                return Ok(());
+            }
+            if starts_value(self.buffer[self.pos]) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Moves past the end of the current line, reading the rest of it from `input` if it is not
+    /// buffered yet.
+    fn skip_line<R: BufRead + ?Sized>(&mut self, input: &mut R) -> std::io::Result<()> {
+        if !self.buffer[self.pos..].contains(&b'\n') {
+            self.pos = self.buffer.len();
+            self.read_line(input)?;
+        }
+        match self.buffer[self.pos..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+        {
+            Some(n) => {
+                self.pos += n + 1;
+                self.line += 1;
+            }
+            None => self.pos = self.buffer.len(),
+        }
+        Ok(())
+    }
+}
+
+/// Passes on the bytes read from `input`, keeping a copy of each of them in `read`
+struct Recorded<'a, R: ?Sized> {
+    input: &'a mut R,
+    read: Vec<u8>,
+}
+
+impl<R: BufRead + ?Sized> Read for Recorded<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.input.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.read.extend_from_slice(&available[..n]);
+        self.input.consume(n);
+        Ok(n)
+    }
+}
+
+/// Whether a line starting with `byte` could be the start of a record.
+fn starts_value(byte: u8) -> bool {
+    matches!(
+        byte,
+        b'{' | b'[' | b'"' | b'-' | b'0'..=b'9' | b't' | b'f' | b'n'
+    ) || byte == RECORD_SEPARATOR as u8
+}
+
+/// The number of newlines in `text`.
+pub fn count_lines(text: &[u8]) -> usize {
+    text.iter().filter(|&&byte| byte == b'\n').count()
+}
+
+/// Whitespace or a record separator, either of which may come between records.
+fn is_separator(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r') || byte == RECORD_SEPARATOR as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str) -> Vec<(usize, Result<String, String>)> {
+        let mut reader = RecordReader::default();
+        let mut input = input.as_bytes();
+        std::iter::from_fn(|| reader.next_record(&mut input))
+            .map(|(line, record)| {
+                let record = record
+                    .map(|record| record.get().to_string())
+                    .map_err(|e| e.to_string());
+                (line, record)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_read_concatenated_records() {
+        let records = read("{\"a\":\"}{\"}{\"b\":[1]}\"c\" 4 true\n5");
+        let texts: Vec<&str> = records
+            .iter()
+            .map(|(_, record)| record.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            texts,
+            ["{\"a\":\"}{\"}", "{\"b\":[1]}", "\"c\"", "4", "true", "5"]
+        );
+    }
+
+    #[test]
+    fn test_read_record_separated_sequence() {
+        let records = read("\u{1e}{\"a\":1}\n\u{1e}{\"b\":\n2}\n\u{1e}3");
+        assert_eq!(
+            records,
+            vec![
+                (0, Ok("{\"a\":1}".to_string())),
+                (1, Ok("{\"b\":\n2}".to_string())),
+                (3, Ok("3".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_mixed_framings() {
+        // A compact record followed by pretty-printed ones
+        let records = read("{\"a\":1}\n{\n  \"b\": 2\n}\n{\n  \"c\": 3\n}");
+        let lines: Vec<usize> = records.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [0, 1, 4]);
+        assert!(records.iter().all(|(_, record)| record.is_ok()));
+    }
+
+    #[test]
+    fn test_resume_after_broken_records() {
+        let records = read(concat!(
+            "{\"a\":1}\n",
+            "{\"b\":\n",
+            "{\"c\":3}\n",
+            "\u{ff}\n",
+            "{\"d\":\"4\n",
+            "{\n  \"e\": \n}\n",
+            "{\"f\":6}"
+        ));
+        let results: Vec<(usize, bool)> = records
+            .iter()
+            .map(|(line, record)| (*line, record.is_ok()))
+            .collect();
+        assert_eq!(
+            results,
+            [
+                (0, true),
+                (1, false),
+                (2, true),
+                (3, false),
+                (4, false),
+                (5, false),
+                (8, true)
+            ]
+        );
+    }
+}
@@ -1,63 +1,172 @@
-use super::{JsonReader, JsonSource};
-use eyre::{eyre, Result};
-use serde_json::Value;
+use super::{
+    file::{each_record, stream_entries, strict_json},
+    Entries, EntryKey, JsonReader, JsonSource, Order, RecordReader,
+};
+use crate::compression::decompress;
+use eyre::{eyre, Report, Result};
+use serde::de::DeserializeOwned;
+use serde_json::{value::RawValue, Value};
 use std::{
-    collections::HashMap,
-    io::{stdin, BufRead, BufReader, Read, Stdin},
+    collections::BTreeMap,
+    io::{stdin, BufRead, BufReader, Read},
     sync::{Arc, Mutex},
 };
 
 #[derive(Clone)]
 pub struct InputStdin {
-    reader: Arc<Mutex<BufReader<Stdin>>>,
+    reader: Arc<Mutex<Box<dyn BufRead + Send>>>,
+    lenient: bool,
 }
 
 impl InputStdin {
-    pub fn new() -> Self {
-        Self {
-            reader: Arc::new(Mutex::new(BufReader::new(stdin()))),
-        }
+    /// Wraps stdin, decompressing it if its first bytes show it is compressed.
+    pub fn new() -> Result<Self> {
+        let reader = decompress(BufReader::new(stdin()), None)?;
+        Ok(Self {
+            reader: Arc::new(Mutex::new(reader)),
+            lenient: false,
+        })
     }
 }
 
-impl JsonSource for InputStdin {
-    fn get_entries(&self, _sort: bool) -> Vec<(String, Value)> {
-        let mut entries = Vec::new();
-        let reader = &mut self.reader.lock().expect("Failed to lock stdin reader");
-        let mut buf = String::new();
-        while reader.read_line(&mut buf).is_ok() {
-            if buf.is_empty() {
-                break;
-            }
-            match serde_json::from_str(&buf) {
-                Ok(entry) => entries.push(entry),
-                Err(e) => {
-                    log::error!("Error parsing JSON: {}", e);
+impl InputStdin {
+    fn each_entry<V: DeserializeOwned>(
+        &self,
+        include: &dyn Fn(&str) -> bool,
+        callback: &mut dyn FnMut(EntryKey, V) -> Result<()>,
+    ) -> Result<()> {
+        let mut reader = self.reader.lock().map_err(|e| eyre!("{e}"))?;
+        if !self.lenient {
+            return stream_entries(&mut *reader, include, callback);
+        }
+        // Lenient input is checked as a whole before any entry is handed out
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        let content = strict_json(&content, true, "stdin")?;
+        stream_entries(content.as_bytes(), include, callback)
+    }
+
+    /// Reads one entry per record with `parse`, which is given the record and the line it starts
+    /// on, logging and skipping records that fail to parse. Records may be one per line,
+    /// pretty-printed, concatenated, or an RFC 7464 sequence. JSON5 records cannot be told apart
+    /// by a JSON deserializer, so lenient input is read one record per line.
+    fn line_entries<V: DeserializeOwned + 'static>(
+        &self,
+        parse: fn(&str, usize) -> Result<(String, V)>,
+    ) -> Result<Entries<'_, V>> {
+        let mut reader = self.reader.lock().map_err(|e| eyre!("{e}"))?;
+        let lenient = self.lenient;
+        let mut records = RecordReader::default();
+        Ok(Box::new(std::iter::from_fn(move || loop {
+            let (start, record) = match lenient {
+                true => {
+                    let (start, line) = records.next_line(&mut *reader)?;
+                    match line {
+                        Ok(line) => (start, Ok(String::from_utf8_lossy(&line).into_owned())),
+                        Err(e) => return Some(Err(e.into())),
+                    }
                 }
+                false => {
+                    let (start, record) = records.next_record(&mut *reader)?;
+                    match record {
+                        Err(e) if e.is_io() => return Some(Err(e.into())),
+                        record => (start, record.map(|record| record.get().to_string())),
+                    }
+                }
+            };
+            match record
+                .map_err(Report::from)
+                .and_then(|record| Ok(strict_json(&record, lenient, "stdin")?.into_owned()))
+                .and_then(|record| parse(&record, start))
+            {
+                Ok(entry) => return Some(Ok(entry)),
+                Err(e) => log::error!("Error parsing JSON on line {start}: {e}"),
             }
-            buf.clear();
+        })))
+    }
+}
+
+/// Reads an entry from a single-key `{"key": value}` object, as written by `split`, a
+/// `["key", value]` pair, or a `{"key": "key", "value": value}` record.
+fn parse_entry<V: DeserializeOwned>(record: &str, _line: usize) -> Result<(String, V)> {
+    if record.trim_start().starts_with('[') {
+        return serde_json::from_str(record)
+            .map_err(|e| eyre!("Expected a [key, value] pair: {e}"));
+    }
+    let mut object: BTreeMap<String, Box<RawValue>> = serde_json::from_str(record)
+        .map_err(|e| eyre!("Expected an object or a [key, value] pair: {e}"))?;
+    if let (1, Some((key, value))) = (object.len(), object.first_key_value()) {
+        return Ok((key.clone(), serde_json::from_str(value.get())?));
+    }
+    let count = object.len();
+    match (object.remove("key"), object.remove("value")) {
+        (Some(key), Some(value)) if object.is_empty() => {
+            let key = serde_json::from_str(key.get())
+                .map_err(|_| eyre!("The key of a key/value record must be a string, not {key}"))?;
+            Ok((key, serde_json::from_str(value.get())?))
         }
-        entries
+        _ => Err(eyre!(
+            "Expected a single-key object or a key/value record, found an object with {count} keys"
+        )),
+    }
+}
+
+/// Reads a whole record as a value, named by the line it starts on.
+fn parse_record<V: DeserializeOwned>(record: &str, line: usize) -> Result<(String, V)> {
+    Ok((line.to_string(), serde_json::from_str(record)?))
+}
+
+impl JsonSource for InputStdin {
+    fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Entries come in whatever order the stream has them, so they are all read and sorted
+    /// before the first is handed out when `order` sorts.
+    fn entries(&self, order: Order) -> Result<Entries<'_>> {
+        order.sort(self.line_entries(parse_entry)?)
     }
 
-    fn read_entry(&self) -> Result<(String, Value)> {
-        let mut buf = String::new();
-        self.read_line(&mut buf)?;
-        Ok(serde_json::from_str(&buf)?)
+    fn raw_entries(&self, order: Order) -> Result<Entries<'_, Box<RawValue>>> {
+        order.sort(self.line_entries(parse_entry)?)
+    }
+
+    fn records(&self) -> Result<Entries<'_>> {
+        self.line_entries(parse_record)
+    }
+
+    fn raw_records(&self) -> Result<Entries<'_, Box<RawValue>>> {
+        self.line_entries(parse_record)
     }
 }
 
 impl JsonReader for InputStdin {
-    fn get_object(&self) -> Result<HashMap<String, Value>> {
-        let mut buffer = String::new();
-        stdin().read_to_string(&mut buffer)?;
-        Ok(serde_json::from_str(&buffer)?)
+    fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
     }
 
-    fn read_line(&self, buf: &mut String) -> Result<()> {
+    fn for_each_record(
+        &self,
+        callback: &mut dyn FnMut(usize, serde_json::Result<Box<RawValue>>) -> Result<()>,
+    ) -> Result<()> {
         let mut reader = self.reader.lock().map_err(|e| eyre!("{e}"))?;
-        reader.read_line(buf)?;
-        Ok(())
+        each_record(&mut *reader, callback)
+    }
+
+    fn for_each_entry(
+        &self,
+        include: &dyn Fn(&str) -> bool,
+        callback: &mut dyn FnMut(EntryKey, Value) -> Result<()>,
+    ) -> Result<()> {
+        self.each_entry(include, callback)
+    }
+
+    fn for_each_raw_entry(
+        &self,
+        include: &dyn Fn(&str) -> bool,
+        callback: &mut dyn FnMut(EntryKey, Box<RawValue>) -> Result<()>,
+    ) -> Result<()> {
+        self.each_entry(include, callback)
     }
 }
 
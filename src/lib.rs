@@ -1,3 +1,5 @@
+/// Detect and decode compressed streams
+pub mod compression;
 /// Read from inputs
 pub mod input;
 /// Write to outputs
@@ -1,9 +1,20 @@
-use clap::{Parser, Subcommand};
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
+use eyre::{Report, Result};
 use jsrmx::{
-    input::{InputDirectory, JsonReaderInput, JsonSourceInput},
-    output::{JsonAppendableOutput, JsonWritableOutput},
-    processor::{json, NdjsonBundler, NdjsonUnbundler},
+    compression::Compression,
+    input::{
+        ErrorPolicy, FileFilter, FileKey, Glob, JsonReaderInput, JsonSourceInput, OnError,
+        Recursion,
+    },
+    output::{JsonAppendableOutput, JsonWritableOutput, Writeable},
+    processor::{
+        json::{self, MergeOptions},
+        template::Template,
+        FilenameField, NdjsonBundler, NdjsonUnbundler,
+    },
 };
+use regex::Regex;
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "jsrmx")]
@@ -13,56 +24,213 @@ struct Cli {
     command: Commands,
 }
 
+#[derive(Args)]
+struct CompressionArgs {
+    /// Compress output with gzip, zstd or bzip2 (default: from the output file extension)
+    #[arg(long)]
+    compress: Option<Compression>,
+    /// Compression level, 1-9 for gzip and bzip2 or 1-22 for zstd
+    #[arg(long)]
+    compression_level: Option<u32>,
+}
+
+impl CompressionArgs {
+    /// Applies the options to `output`, logging and returning `false` if they are invalid.
+    fn apply(&self, output: &mut dyn Writeable) -> bool {
+        if self.compress.is_none() && self.compression_level.is_none() {
+            return true;
+        }
+        output
+            .set_compression(self.compress, self.compression_level)
+            .map_err(|e| log::error!("Error setting up compression: {e}"))
+            .is_ok()
+    }
+}
+
+#[derive(Args)]
+struct SourcesArgs {
+    /// Input directory, file or `-` for stdin
+    #[arg(value_name = "INPUT")]
+    input: String,
+    /// Output filename or `-` for stdout
+    #[arg(default_value = "-")]
+    output: String,
+    /// Another input directory, file or `-` for stdin, read after the ones before it. Inputs take
+    /// precedence in the order given
+    #[arg(short, long = "input", value_name = "INPUT")]
+    inputs: Vec<String>,
+    /// Only read the files of input directories matching this glob (default: `*.json`)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<Glob>,
+    /// Skip the files and subdirectories of input directories matching this glob
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<Glob>,
+    /// What to do with an input file, or a file of an input directory, that cannot be read: fail,
+    /// skip or quarantine
+    #[arg(long, value_name = "POLICY", default_value = "fail")]
+    on_error: OnError,
+    /// Directory to copy unreadable files into with --on-error=quarantine
+    #[arg(long, value_name = "DIR", required_if_eq("on_error", "quarantine"))]
+    quarantine_dir: Option<PathBuf>,
+}
+
+impl SourcesArgs {
+    /// Opens the inputs, then the output, exiting with a usage error if any of them is invalid.
+    /// The returned policy records the files of the inputs that could not be read.
+    fn open(self) -> (Vec<JsonSourceInput>, JsonAppendableOutput, ErrorPolicy) {
+        let filter = FileFilter::new(self.include, self.exclude);
+        let policy = ErrorPolicy::new(self.on_error, self.quarantine_dir)
+            .unwrap_or_else(|e| usage_error(e.to_string()));
+        let inputs = std::iter::once(&self.input)
+            .chain(&self.inputs)
+            .map(|path| {
+                let input: JsonSourceInput = path
+                    .parse()
+                    .unwrap_or_else(|e| usage_error(format!("invalid input '{path}': {e}")));
+                {
+                    let mut source = input.write().expect("Error acquiring write lock on input");
+                    source.set_file_filter(filter.clone());
+                    source.set_error_policy(policy.clone());
+                }
+                input
+            })
+            .collect();
+        let output = self.output;
+        let output = output
+            .parse()
+            .unwrap_or_else(|e| usage_error(format!("invalid output '{output}': {e}")));
+        (inputs, output, policy)
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Merges multiple single-object <dir>/${key}.json files into one json object.
     Merge {
+        /// Write the values as one array, in index or sorted order, instead of an object
+        #[arg(short, long, default_value_t = false)]
+        array: bool,
         /// Compact JSON output
         #[arg(short, long, conflicts_with = "pretty", default_value_t = false)]
         compact: bool,
-        /// Target input directory
-        input: JsonSourceInput,
-        /// Output filename or `-` for stdout
-        #[arg(default_value = "-")]
-        output: JsonAppendableOutput,
+        #[command(flatten)]
+        sources: SourcesArgs,
+        #[command(flatten)]
+        compression: CompressionArgs,
         /// Only split keys matching regex filter
         #[arg(short, long)]
         filter: Option<String>,
+        /// Accept JSON5/JSONC input, such as comments and trailing commas
+        #[arg(short, long, default_value_t = false)]
+        lenient: bool,
         /// Pretty-print output files
         #[arg(short, long, default_value_t = true)]
         pretty: bool,
+        /// Copy values byte for byte instead of re-serializing them
+        #[arg(long, conflicts_with = "tag_source", default_value_t = false)]
+        raw: bool,
+        /// Read subdirectories, so `<dir>/a/b.json` becomes `{"a": {"b": ...}}`
+        #[arg(short, long, default_value_t = false)]
+        recursive: bool,
+        /// With --recursive, name entries by their path, like `a/b`, instead of nesting them
+        #[arg(long, requires = "recursive", default_value_t = false)]
+        flatten: bool,
+        /// With --recursive, the number of subdirectory levels to read
+        #[arg(long, requires = "recursive")]
+        max_depth: Option<usize>,
         /// Alphabetically sort object keys
         #[arg(short, long, default_value_t = false)]
         sort: bool,
+        /// Add this field to each object value, holding the input it was read from
+        #[arg(long, value_name = "FIELD")]
+        tag_source: Option<String>,
+        /// Suffix to trim from file names to make keys, like `.json.gz`
+        #[arg(short, long, value_name = "SUFFIX", group = "key")]
+        trim: Option<String>,
+        /// Use the whole file name, extensions included, as the key
+        #[arg(long, group = "key", default_value_t = false)]
+        full_name: bool,
+        /// Take the key from the `key` group, or else the first group, of this regex matched
+        /// against the file name
+        #[arg(long, value_name = "REGEX", group = "key")]
+        key_regex: Option<Regex>,
+        /// Take the key from this string or number field of each file, like `metadata.name`
+        #[arg(long, value_name = "FIELD", group = "key")]
+        key_field: Option<String>,
+        /// Fail on a key read more than once, instead of keeping the first one
+        #[arg(long, default_value_t = false)]
+        unique_keys: bool,
     },
-    /// Splits single JSON object into multiple json objects.
+    /// Splits single JSON object or array into multiple json objects.
     Split {
         /// Compact JSON output
         #[arg(short, long, conflicts_with = "pretty", default_value_t = false)]
         compact: bool,
+        /// Split nested objects this many levels deep into subdirectories, like <dir>/a/b.json
+        #[arg(short, long, default_value_t = 0)]
+        depth: usize,
         /// Input filename or `-` for stdin
         #[arg(default_value = "-")]
         input: JsonReaderInput,
         /// Target output directory or `-` for stdout
         #[arg(default_value = "-")]
         output: JsonWritableOutput,
+        #[command(flatten)]
+        compression: CompressionArgs,
         /// Only split keys matching regex filter
         #[arg(short, long)]
         filter: Option<String>,
+        /// Accept JSON5/JSONC input, such as comments and trailing commas
+        #[arg(short, long, default_value_t = false)]
+        lenient: bool,
+        /// List of field names to name array elements by, uses first non-null value
+        #[arg(short, long, value_delimiter = ',')]
+        name: Option<Vec<String>>,
         /// Pretty-print output files
         #[arg(short, long, default_value_t = true)]
         pretty: bool,
+        /// Copy values byte for byte instead of re-serializing them
+        #[arg(long, default_value_t = false)]
+        raw: bool,
+        /// Fail on an entry name that is unsafe as a filename, instead of rewriting it
+        #[arg(long, default_value_t = false)]
+        strict_names: bool,
     },
     /// Bundles multiple <dir>/*.json files into one ndjson file
     Bundle {
-        /// Target input directory
-        dir: InputDirectory,
-        /// Output filename or `-` for stdout
-        #[arg(default_value = "-")]
-        output: JsonAppendableOutput,
+        #[command(flatten)]
+        sources: SourcesArgs,
+        #[command(flatten)]
+        compression: CompressionArgs,
         /// String-escaped nested JSON fields to escape
         #[arg(short, long, value_delimiter = ',')]
         escape: Option<Vec<String>>,
+        /// Write an RFC 7464 JSON text sequence, starting each record with a record separator
+        #[arg(long, default_value_t = false)]
+        json_seq: bool,
+        /// Accept JSON5/JSONC input, such as comments and trailing commas
+        #[arg(short, long, default_value_t = false)]
+        lenient: bool,
+        /// Copy records byte for byte, only removing whitespace between tokens
+        #[arg(
+            long,
+            conflicts_with_all = ["escape", "tag_source", "filename_field"],
+            default_value_t = false
+        )]
+        raw: bool,
+        /// Bundle the files of subdirectories too
+        #[arg(short, long, default_value_t = false)]
+        recursive: bool,
+        /// Add this field to each record, holding the input it was read from
+        #[arg(long, value_name = "FIELD")]
+        tag_source: Option<String>,
+        /// Add this field to each record, holding the path of the file it was read from
+        #[arg(long, value_name = "FIELD")]
+        filename_field: Option<String>,
+        /// Wrap each record in this field of an object holding its path, instead of adding the
+        /// path to the record
+        #[arg(long, value_name = "FIELD", requires = "filename_field")]
+        envelope: Option<String>,
     },
     /// Unbundle single [input] file into multiple json objects
     Unbundle {
@@ -75,6 +243,8 @@ enum Commands {
         /// Target output directory or `-` for stdout
         #[arg(default_value = "-")]
         output: JsonWritableOutput,
+        #[command(flatten)]
+        compression: CompressionArgs,
         /// List of field names to read for filename, uses first non-null value
         #[arg(short, long, value_delimiter = ',')]
         name: Option<Vec<String>>,
@@ -84,9 +254,27 @@ enum Commands {
         /// Pretty-print output objects
         #[arg(short, long, default_value_t = true)]
         pretty: bool,
+        /// Copy records byte for byte instead of re-serializing them
+        #[arg(long, conflicts_with_all = ["unescape", "filename_field"], default_value_t = false)]
+        raw: bool,
         /// String-escaped nested JSON fields to unescape
         #[arg(short, long, value_delimiter = ',')]
         unescape: Option<Vec<String>>,
+        /// Name each record by the path `bundle --filename-field` kept in this field, then remove it
+        #[arg(long, value_name = "FIELD")]
+        filename_field: Option<String>,
+        /// Read each record from this field, as `bundle --envelope` wrapped it
+        #[arg(long, value_name = "FIELD", requires = "filename_field")]
+        envelope: Option<String>,
+        /// Template to name each record by, like `{type}/{attributes.title|slug}-{id}.json`
+        #[arg(short, long, value_name = "TEMPLATE", conflicts_with_all = ["name", "type"])]
+        filename: Option<Template>,
+        /// The index of the first record, as given to `{#}` in a filename template
+        #[arg(long, value_name = "N", requires = "filename", default_value_t = 0)]
+        index_start: usize,
+        /// Fail on an entry name that is unsafe as a filename, instead of rewriting it
+        #[arg(long, default_value_t = false)]
+        strict_names: bool,
     },
 }
 
@@ -105,67 +293,172 @@ fn main() {
 
     match cli.command {
         Commands::Merge {
+            array,
             compact,
-            input,
-            output,
+            sources,
+            compression,
             filter,
+            lenient,
             pretty,
+            raw,
+            recursive,
+            flatten,
+            max_depth,
             sort,
+            tag_source,
+            trim,
+            full_name,
+            key_regex,
+            key_field,
+            unique_keys,
         } => {
-            let entries = input.get_entries(sort);
-            let merged_object = json::merge(entries, filter);
+            let keys = match (trim, full_name, key_regex, key_field) {
+                (Some(suffix), ..) => FileKey::Trim(suffix),
+                (_, true, ..) => FileKey::FullName,
+                (.., Some(regex), _) if regex.captures_len() < 2 => {
+                    usage_error(format!("--key-regex '{regex}' has no capture group"))
+                }
+                (.., Some(regex), _) => FileKey::Capture(regex),
+                (.., Some(field)) => FileKey::Field(field),
+                _ => FileKey::Stem,
+            };
+            let (inputs, output, policy) = sources.open();
+            let recursion = recursive.then_some(Recursion { max_depth, flatten });
+            for input in &inputs {
+                let mut input = input.write().expect("Error acquiring write lock on input");
+                input.set_lenient(lenient);
+                input.set_recursion(recursion);
+                input.set_file_key(keys.clone());
+            }
             if pretty && !compact {
                 output
                     .write()
                     .expect("Error acquiring write lock on output")
                     .set_pretty(true);
             }
-            output
-                .read()
-                .expect("Error acquiring read lock on output")
-                .append(merged_object)
-                .unwrap_or_else(|e| log::error!("Error writing to output: {e}"));
+            let configured = compression.apply(
+                &mut *output
+                    .write()
+                    .expect("Error acquiring write lock on output"),
+            );
+            if !configured {
+                return;
+            }
+            let options = MergeOptions {
+                filter,
+                sort,
+                raw,
+                array,
+                tag: tag_source,
+                unique_keys,
+            };
+            let merged = json::stream_merge(&inputs, &output, options);
+            policy.summarize();
+            finish("merging", merged, output.flush());
         }
         Commands::Split {
             compact,
+            depth,
             input,
             output,
+            compression,
             filter,
+            lenient,
+            name,
             pretty,
+            raw,
+            strict_names,
         } => {
+            input
+                .write()
+                .expect("Error acquiring write lock on input")
+                .set_lenient(lenient);
             if pretty && !compact {
                 output
                     .write()
                     .expect("Error acquiring write lock on output")
                     .set_pretty(true);
-            };
-            let object = input.get_object().expect("Error reading input: {input:?}");
-            let entries = json::split(object, filter);
+            }
             output
-                .read()
-                .expect("Error acquiring read lock on output")
-                .write_entries(entries)
-                .unwrap_or_else(|e| {
-                    log::error!("Error splitting: {e}");
-                });
+                .write()
+                .expect("Error acquiring write lock on output")
+                .set_strict_names(strict_names);
+            let configured = compression.apply(
+                &mut *output
+                    .write()
+                    .expect("Error acquiring write lock on output"),
+            );
+            if !configured {
+                return;
+            }
+            let split = json::stream_split(&input, &output, filter, raw, name, depth);
+            finish("splitting", split, output.flush());
         }
         Commands::Bundle {
-            dir,
+            sources,
             escape,
-            output,
-        } => NdjsonBundler::new(dir, output)
-            .bundle(escape)
-            .unwrap_or_else(|e| {
-                log::error!("Error bundling: {e}");
-            }),
+            compression,
+            json_seq,
+            lenient,
+            raw,
+            recursive,
+            tag_source,
+            filename_field,
+            envelope,
+        } => {
+            let (inputs, output, policy) = sources.open();
+            // Subdirectories are bundled file by file, so their entries are always flattened
+            let recursion = recursive.then_some(Recursion {
+                max_depth: None,
+                flatten: true,
+            });
+            for input in &inputs {
+                let mut input = input.write().expect("Error acquiring write lock on input");
+                input.set_lenient(lenient);
+                input.set_recursion(recursion);
+                if filename_field.is_some() {
+                    input.set_file_key(FileKey::FullName);
+                }
+            }
+            output
+                .write()
+                .expect("Error acquiring write lock on output")
+                .set_json_seq(json_seq);
+            let configured = compression.apply(
+                &mut *output
+                    .write()
+                    .expect("Error acquiring write lock on output"),
+            );
+            if !configured {
+                return;
+            }
+            let bundler = NdjsonBundler::new(inputs, output.clone());
+            let bundled = match raw {
+                true => bundler.bundle_raw(),
+                false => bundler.bundle(
+                    escape,
+                    tag_source,
+                    filename_field.map(|field| FilenameField { field, envelope }),
+                ),
+            };
+            policy.summarize();
+            finish("bundling", bundled, output.flush());
+        }
         Commands::Unbundle {
             compact,
             input,
             name,
             output,
+            compression,
             pretty,
+            raw,
             r#type,
             unescape,
+            filename_field,
+            envelope,
+            filename,
+            index_start,
+            strict_names,
         } => {
             if pretty && !compact {
                 output
@@ -173,11 +466,57 @@ fn main() {
                     .expect("Error acquiring write lock on output")
                     .set_pretty(true);
             }
-            NdjsonUnbundler::new(input, output, unescape)
-                .unbundle(name, r#type)
-                .unwrap_or_else(|e| {
-                    log::error!("Error unbundling: {e}");
-                })
+            output
+                .write()
+                .expect("Error acquiring write lock on output")
+                .set_strict_names(strict_names);
+            let configured = compression.apply(
+                &mut *output
+                    .write()
+                    .expect("Error acquiring write lock on output"),
+            );
+            if !configured {
+                return;
+            }
+            let mut unbundler = NdjsonUnbundler::new(input, output.clone(), unescape, raw);
+            unbundler
+                .set_filename_field(filename_field.map(|field| FilenameField { field, envelope }));
+            unbundler.set_template(filename.map(|template| template.starting_at(index_start)));
+            let unbundled = unbundler.unbundle(name, r#type);
+            finish("unbundling", unbundled, output.flush());
+        }
+    }
+}
+
+/// Exits with a usage error, as clap does for arguments it cannot parse.
+fn usage_error(message: String) -> ! {
+    Cli::command()
+        .error(ErrorKind::ValueValidation, message)
+        .exit()
+}
+
+/// Logs the error of a failed command, then the error from flushing its output, and exits with a
+/// failure status if there was either. A closed stdout pipe, as when piping into `head`, ends the
+/// command early but is not treated as an error.
+fn finish(action: &str, result: Result<()>, flushed: Result<()>) {
+    let mut failed = false;
+    for e in [result.err(), flushed.err()].into_iter().flatten() {
+        if is_broken_pipe(&e) {
+            log::debug!("Output closed while {action}");
+            return;
         }
+        log::error!("Error {action}: {e}");
+        failed = true;
     }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn is_broken_pipe(e: &Report) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+    })
 }
@@ -1,25 +1,91 @@
 mod directory;
 mod file;
+mod filename;
 mod stream;
+use crate::compression::Compression;
 use directory::DirectoryOutput;
 use eyre::{eyre, Report, Result};
 use file::FileOutput;
 use stream::StreamOutput;
 
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{value::RawValue, Value};
 use std::{
+    collections::BTreeMap,
+    io::Write,
     ops::Deref,
     path::PathBuf,
     sync::{Arc, RwLock},
 };
 
 pub trait Appendable: Writeable {
+    /// Starts every appended record with an RFC 7464 record separator, so the output is a JSON
+    /// text sequence rather than NDJSON.
+    fn set_json_seq(&mut self, json_seq: bool);
     fn append(&self, content: Value) -> std::io::Result<()>;
+    /// Appends a single object, writing each `"key": value` pair as soon as `entries` yields it.
+    /// An error from `entries` is returned without closing the object.
+    fn append_object(
+        &self,
+        entries: &mut dyn Iterator<Item = std::io::Result<(String, Value)>>,
+    ) -> std::io::Result<()>;
+    /// Appends a value exactly as it was read, without re-serializing it.
+    fn append_raw(&self, content: Box<RawValue>) -> std::io::Result<()>;
+    /// Like `append_object`, but copies each value exactly as it was read.
+    fn append_raw_object(
+        &self,
+        entries: &mut dyn Iterator<Item = std::io::Result<(String, Box<RawValue>)>>,
+    ) -> std::io::Result<()>;
+    /// Appends a single array, writing each element as soon as `values` yields it.
+    fn append_array(
+        &self,
+        values: &mut dyn Iterator<Item = std::io::Result<Value>>,
+    ) -> std::io::Result<()>;
+    /// Like `append_array`, but copies each element exactly as it was read.
+    fn append_raw_array(
+        &self,
+        values: &mut dyn Iterator<Item = std::io::Result<Box<RawValue>>>,
+    ) -> std::io::Result<()>;
 }
 
 pub trait Writeable: Send + Sync {
     fn set_pretty(&mut self, pretty: bool);
+    /// Fails to write an entry whose name is unsafe as a filename, rather than rewriting it. Only
+    /// directory outputs name files by their entries, so by default this does nothing.
+    fn set_strict_names(&mut self, _strict: bool) {}
+    /// Makes a name taken from record content safe to use as one component of an entry name,
+    /// so that it cannot start a subdirectory. Only directory outputs name files by their entries,
+    /// so by default the name is kept as it is.
+    fn escape_component(&self, name: &str) -> std::io::Result<String> {
+        Ok(name.to_string())
+    }
+    /// Compresses the output with `compression`, or with the codec implied by the output's file
+    /// extension when `None`. Must be called before anything is written.
+    fn set_compression(
+        &mut self,
+        compression: Option<Compression>,
+        level: Option<u32>,
+    ) -> std::io::Result<()>;
+    fn write_entry(&self, key: String, value: Value) -> std::io::Result<()>;
     fn write_entries(&self, entries: Vec<(String, Value)>) -> std::io::Result<()>;
+    /// Writes entries named by a whole filename, extension included, such as the paths restored
+    /// by `unbundle`. Only directory outputs name files by their entries, so by default a `.json`
+    /// extension is dropped and the entries are written as usual.
+    fn write_files(&self, entries: Vec<(String, Value)>) -> std::io::Result<()> {
+        let entries = entries
+            .into_iter()
+            .map(|(filename, value)| match filename.strip_suffix(".json") {
+                Some(key) => (key.to_string(), value),
+                None => (filename, value),
+            })
+            .collect();
+        self.write_entries(entries)
+    }
+    /// Writes an entry whose value is copied exactly as it was read.
+    fn write_raw_entry(&self, key: String, value: Box<RawValue>) -> std::io::Result<()>;
+    fn write_raw_entries(&self, entries: Vec<(String, Box<RawValue>)>) -> std::io::Result<()>;
+    /// Writes out anything still buffered. Called once the command has finished, or failed.
+    fn flush(&self) -> std::io::Result<()>;
 }
 
 #[derive(Clone)]
@@ -52,6 +118,15 @@ impl std::str::FromStr for JsonAppendableOutput {
     }
 }
 
+impl JsonAppendableOutput {
+    pub fn flush(&self) -> Result<()> {
+        self.read()
+            .map_err(|_| eyre!("Error acquiring read lock on output"))?
+            .flush()?;
+        Ok(())
+    }
+}
+
 impl Deref for JsonAppendableOutput {
     type Target = Arc<RwLock<dyn Appendable>>;
 
@@ -92,6 +167,15 @@ impl std::str::FromStr for JsonWritableOutput {
     }
 }
 
+impl JsonWritableOutput {
+    pub fn flush(&self) -> Result<()> {
+        self.read()
+            .map_err(|_| eyre!("Error acquiring read lock on output"))?
+            .flush()?;
+        Ok(())
+    }
+}
+
 impl Deref for JsonWritableOutput {
     type Target = Arc<RwLock<dyn Writeable>>;
 
@@ -99,3 +183,136 @@ impl Deref for JsonWritableOutput {
         &self.0
     }
 }
+
+/// Writes `content` on its own line. Raw values inside `content` are copied verbatim even when
+/// pretty-printing.
+fn write_line<W: Write, T: Serialize + ?Sized>(
+    writer: &mut W,
+    pretty: bool,
+    content: &T,
+) -> std::io::Result<()> {
+    match pretty {
+        true => serde_json::to_writer_pretty(&mut *writer, content)?,
+        false => serde_json::to_writer(&mut *writer, content)?,
+    }
+    writeln!(writer)
+}
+
+/// Writes a `{key: value}` object on its own line.
+fn write_entry_line<W: Write, V: Serialize>(
+    writer: &mut W,
+    pretty: bool,
+    key: String,
+    value: V,
+) -> std::io::Result<()> {
+    write_line(writer, pretty, &BTreeMap::from([(key, value)]))
+}
+
+/// Writes `entries` as the members of one JSON object without collecting them first. Pretty output
+/// matches the layout of `serde_json::to_writer_pretty`.
+fn write_object<W: Write>(
+    writer: &mut W,
+    pretty: bool,
+    entries: &mut dyn Iterator<Item = std::io::Result<(String, Value)>>,
+) -> std::io::Result<()> {
+    write_members(writer, pretty, *b"{}", entries, |writer, (key, value)| {
+        write_key(writer, pretty, &key)?;
+        write_value(writer, pretty, &value)
+    })
+}
+
+/// Like `write_object`, but copies each value verbatim. Only the object around the values is
+/// affected by `pretty`.
+fn write_raw_object<W: Write>(
+    writer: &mut W,
+    pretty: bool,
+    entries: &mut dyn Iterator<Item = std::io::Result<(String, Box<RawValue>)>>,
+) -> std::io::Result<()> {
+    write_members(writer, pretty, *b"{}", entries, |writer, (key, value)| {
+        write_key(writer, pretty, &key)?;
+        writer.write_all(value.get().as_bytes())
+    })
+}
+
+/// Writes `values` as the elements of one JSON array without collecting them first.
+fn write_array<W: Write>(
+    writer: &mut W,
+    pretty: bool,
+    values: &mut dyn Iterator<Item = std::io::Result<Value>>,
+) -> std::io::Result<()> {
+    write_members(writer, pretty, *b"[]", values, |writer, value| {
+        write_value(writer, pretty, &value)
+    })
+}
+
+/// Like `write_array`, but copies each element verbatim.
+fn write_raw_array<W: Write>(
+    writer: &mut W,
+    pretty: bool,
+    values: &mut dyn Iterator<Item = std::io::Result<Box<RawValue>>>,
+) -> std::io::Result<()> {
+    write_members(writer, pretty, *b"[]", values, |writer, value| {
+        writer.write_all(value.get().as_bytes())
+    })
+}
+
+/// Writes the members of an object or array between `brackets`, one per indented line when
+/// `pretty`, matching the layout of `serde_json::to_writer_pretty`.
+fn write_members<W: Write, T>(
+    writer: &mut W,
+    pretty: bool,
+    [open, close]: [u8; 2],
+    members: &mut dyn Iterator<Item = std::io::Result<T>>,
+    write_member: impl Fn(&mut W, T) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut empty = true;
+    for member in members {
+        // A member that could not be read leaves the brackets unclosed, so the output is not
+        // mistaken for a complete value
+        let member = member?;
+        writer.write_all(&[if empty { open } else { b',' }])?;
+        if pretty {
+            writer.write_all(b"\n  ")?;
+        }
+        write_member(writer, member)?;
+        empty = false;
+    }
+    match (empty, pretty) {
+        (true, _) => writer.write_all(&[open, close]),
+        (false, true) => writer.write_all(&[b'\n', close]),
+        (false, false) => writer.write_all(&[close]),
+    }
+}
+
+fn write_key<W: Write>(writer: &mut W, pretty: bool, key: &str) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *writer, key)?;
+    writer.write_all(if pretty { b": " } else { b":" })
+}
+
+/// Writes a value nested one level deep inside an object or array.
+fn write_value<W: Write>(writer: &mut W, pretty: bool, value: &Value) -> std::io::Result<()> {
+    match pretty {
+        true => Ok(serde_json::to_writer_pretty(Indented(writer), value)?),
+        false => Ok(serde_json::to_writer(writer, value)?),
+    }
+}
+
+/// Indents every line after the first by one level, so a pretty-printed value can be nested
+/// inside an object that is being written by hand.
+struct Indented<'a, W: Write>(&'a mut W);
+
+impl<W: Write> Write for Indented<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for (i, line) in buf.split(|&byte| byte == b'\n').enumerate() {
+            if i > 0 {
+                self.0.write_all(b"\n  ")?;
+            }
+            self.0.write_all(line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
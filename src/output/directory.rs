@@ -1,38 +1,165 @@
-use super::Writeable;
+use super::{
+    filename::{escape_component, sanitize},
+    Writeable,
+};
+use crate::compression::{Compression, Encoder};
 use rayon::prelude::*;
-use serde_json::Value;
+use serde_json::{value::RawValue, Value};
 use std::{
-    fs::{create_dir_all, OpenOptions},
-    io::BufWriter,
-    path::PathBuf,
+    collections::{hash_map::Entry, HashMap},
+    fs::{canonicalize, create_dir_all, File, OpenOptions},
+    io::{BufWriter, Error, ErrorKind, Write},
+    path::{Path, PathBuf},
 };
 
 #[derive(Clone, Debug)]
 pub struct DirectoryOutput {
     pretty: bool,
+    compression: Option<Compression>,
+    level: Option<u32>,
+    strict_names: bool,
     pub path: PathBuf,
 }
 
 impl DirectoryOutput {
     pub fn new(path: PathBuf, pretty: bool) -> Self {
-        Self { pretty, path }
+        Self {
+            pretty,
+            compression: None,
+            level: None,
+            strict_names: false,
+            path,
+        }
     }
 
-    fn write_file(&self, filename: &str, content: Value) -> std::io::Result<()> {
-        let mut path = self.path.clone();
-        path.push(filename);
+    fn create_dir(&self) -> std::io::Result<()> {
+        if self.path != Path::new(".") {
+            create_dir_all(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// The path `filename` is written to in the output directory. When compressing, the codec's
+    /// extension is appended, as in `alpha.json.gz`. Names are taken from record content, so they
+    /// are first made safe to write, and nothing is written through a symlink leading out of the
+    /// directory.
+    fn file_path(&self, filename: &str) -> std::io::Result<PathBuf> {
+        let filename = match self.compression {
+            Some(compression) => format!("{filename}.{}", compression.extension()),
+            None => filename.to_string(),
+        };
+        let path = self.path.join(sanitize(&filename, self.strict_names)?);
+        self.check_inside(&path)?;
+        Ok(path)
+    }
+
+    fn create_file(&self, path: &Path) -> std::io::Result<Encoder<BufWriter<File>>> {
+        // Names split from nested objects, like `a/b.json`, are written into subdirectories
+        if let Some(parent) = path.parent().filter(|parent| *parent != self.path) {
+            create_dir_all(parent)?;
+        }
         log::info!("Writing file {}", path.display());
         let file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
-            .open(&path)?;
-        let mut writer = BufWriter::new(file);
+            .open(path)?;
+        Encoder::new(BufWriter::new(file), self.compression, self.level)
+    }
+
+    /// Refuses to write `path` if it, or the deepest of its directories that already exists,
+    /// resolves through a symlink to somewhere outside the output directory.
+    fn check_inside(&self, path: &Path) -> std::io::Result<()> {
+        let Some(existing) = path
+            .ancestors()
+            .find(|ancestor| ancestor.symlink_metadata().is_ok())
+        else {
+            return Ok(());
+        };
+        let root = canonicalize(&self.path)?;
+        let resolved = canonicalize(existing).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Cannot resolve {}: {e}", existing.display()),
+            )
+        })?;
+        match resolved.starts_with(&root) {
+            true => Ok(()),
+            false => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "Refusing to write {}, it resolves to {} outside the output directory",
+                    path.display(),
+                    resolved.display()
+                ),
+            )),
+        }
+    }
+
+    fn write_file(&self, path: &Path, content: Value) -> std::io::Result<()> {
+        let mut writer = self.create_file(path)?;
         match self.pretty {
             true => serde_json::to_writer_pretty(&mut writer, &content)?,
             false => serde_json::to_writer(&mut writer, &content)?,
         }
-        Ok(())
+        writer.finish()
+    }
+
+    /// Writes `content` byte for byte, ignoring the pretty-print setting.
+    fn write_raw_file(&self, path: &Path, content: &RawValue) -> std::io::Result<()> {
+        let mut writer = self.create_file(path)?;
+        writer.write_all(content.get().as_bytes())?;
+        writer.finish()
+    }
+
+    /// Writes a batch of entries to the files they name in parallel. Entries whose names lead to
+    /// the same file are written once, with the last of them, just as writing them in order would
+    /// leave it. A name that is refused, as in strict mode, or a file that fails to be written is
+    /// returned as an error once the others have been written.
+    fn write_batch<V: Send>(
+        &self,
+        entries: Vec<(String, V)>,
+        write: impl Fn(&Self, &Path, V) -> std::io::Result<()> + Sync,
+    ) -> std::io::Result<()> {
+        self.create_dir()?;
+        let mut files: Vec<(PathBuf, V)> = Vec::with_capacity(entries.len());
+        let mut positions: HashMap<PathBuf, usize> = HashMap::new();
+        let mut refused = None;
+        for (filename, value) in entries {
+            let path = match self.file_path(&filename) {
+                Ok(path) => path,
+                Err(e) => {
+                    log::error!("Error writing to file: {e}");
+                    refused.get_or_insert(e);
+                    continue;
+                }
+            };
+            match positions.entry(path) {
+                Entry::Occupied(position) => {
+                    log::warn!(
+                        "Overwriting {} with a later entry",
+                        position.key().display()
+                    );
+                    files[*position.get()].1 = value;
+                }
+                Entry::Vacant(position) => {
+                    files.push((position.key().clone(), value));
+                    position.insert(files.len() - 1);
+                }
+            }
+        }
+        let failed = files
+            .into_par_iter()
+            .filter_map(|(path, value)| {
+                let e = write(self, &path, value).err()?;
+                log::error!("Error writing to file {}: {e}", path.display());
+                Some(Error::new(
+                    e.kind(),
+                    format!("Cannot write {}: {e}", path.display()),
+                ))
+            })
+            .reduce_with(|first, _| first);
+        refused.or(failed).map_or(Ok(()), Err)
     }
 }
 
@@ -41,19 +168,61 @@ impl Writeable for DirectoryOutput {
         self.pretty = pretty;
     }
 
-    fn write_entries(&self, mut entries: Vec<(String, Value)>) -> std::io::Result<()> {
-        if self.path != PathBuf::from(".") {
-            //log::info!("Creating directory {}", self.path.display());
-            create_dir_all(&self.path)?;
-        }
+    fn set_strict_names(&mut self, strict: bool) {
+        self.strict_names = strict;
+    }
 
-        entries.par_drain(..).for_each(|(key, value)| {
-            self.write_file(&format!("{key}.json"), value)
-                .err()
-                .map(|e| {
-                    log::error!("Error writing to file: {e}");
-                });
-        });
+    fn escape_component(&self, name: &str) -> std::io::Result<String> {
+        escape_component(name, self.strict_names)
+    }
+
+    /// Compresses each file separately. Files are written in parallel, so the compression work is
+    /// spread across the rayon pool.
+    fn set_compression(
+        &mut self,
+        compression: Option<Compression>,
+        level: Option<u32>,
+    ) -> std::io::Result<()> {
+        // Check the level up front rather than failing on every file
+        Encoder::new(std::io::sink(), compression, level)?;
+        self.compression = compression;
+        self.level = level;
         Ok(())
     }
+
+    fn write_entry(&self, key: String, value: Value) -> std::io::Result<()> {
+        self.create_dir()?;
+        self.write_file(&self.file_path(&format!("{key}.json"))?, value)
+    }
+
+    fn write_entries(&self, entries: Vec<(String, Value)>) -> std::io::Result<()> {
+        self.write_files(with_extension(entries))
+    }
+
+    fn write_files(&self, entries: Vec<(String, Value)>) -> std::io::Result<()> {
+        self.write_batch(entries, Self::write_file)
+    }
+
+    fn write_raw_entry(&self, key: String, value: Box<RawValue>) -> std::io::Result<()> {
+        self.create_dir()?;
+        self.write_raw_file(&self.file_path(&format!("{key}.json"))?, &value)
+    }
+
+    fn write_raw_entries(&self, entries: Vec<(String, Box<RawValue>)>) -> std::io::Result<()> {
+        self.write_batch(with_extension(entries), |output, path, value| {
+            output.write_raw_file(path, &value)
+        })
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Names the file of each entry after its key, with the `.json` extension.
+fn with_extension<V>(entries: Vec<(String, V)>) -> Vec<(String, V)> {
+    entries
+        .into_iter()
+        .map(|(key, value)| (format!("{key}.json"), value))
+        .collect()
 }
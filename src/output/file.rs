@@ -1,28 +1,74 @@
-use super::{Appendable, Writeable};
-use serde_json::Value;
+use super::{
+    write_array, write_entry_line, write_line, write_object, write_raw_array, write_raw_object,
+    Appendable, Writeable,
+};
+use crate::{
+    compression::{Compression, Encoder},
+    input::RECORD_SEPARATOR,
+};
+use serde_json::{value::RawValue, Value};
 use std::{
     fs::{File, OpenOptions},
     io::{BufWriter, Write},
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
 };
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct FileOutput {
     pretty: bool,
-    writer: Arc<Mutex<BufWriter<File>>>,
+    json_seq: bool,
+    writer: Arc<Mutex<Encoder<BufWriter<File>>>>,
     pub path: PathBuf,
 }
 
 impl Appendable for FileOutput {
+    fn set_json_seq(&mut self, json_seq: bool) {
+        self.json_seq = json_seq;
+    }
+
     fn append(&self, content: Value) -> std::io::Result<()> {
-        let mut guard = self.writer.lock().expect("Failed to get writer lock");
-        match self.pretty {
-            true => serde_json::to_writer_pretty(&mut *guard, &content)?,
-            false => serde_json::to_writer(&mut *guard, &content)?,
-        }
-        writeln!(&mut *guard)?;
-        Ok(())
+        write_line(&mut *self.start_record()?, self.pretty, &content)
+    }
+
+    fn append_object(
+        &self,
+        entries: &mut dyn Iterator<Item = std::io::Result<(String, Value)>>,
+    ) -> std::io::Result<()> {
+        let mut guard = self.start_record()?;
+        write_object(&mut *guard, self.pretty, entries)?;
+        writeln!(&mut *guard)
+    }
+
+    fn append_raw(&self, content: Box<RawValue>) -> std::io::Result<()> {
+        write_line(&mut *self.start_record()?, false, &content)
+    }
+
+    fn append_raw_object(
+        &self,
+        entries: &mut dyn Iterator<Item = std::io::Result<(String, Box<RawValue>)>>,
+    ) -> std::io::Result<()> {
+        let mut guard = self.start_record()?;
+        write_raw_object(&mut *guard, self.pretty, entries)?;
+        writeln!(&mut *guard)
+    }
+
+    fn append_array(
+        &self,
+        values: &mut dyn Iterator<Item = std::io::Result<Value>>,
+    ) -> std::io::Result<()> {
+        let mut guard = self.start_record()?;
+        write_array(&mut *guard, self.pretty, values)?;
+        writeln!(&mut *guard)
+    }
+
+    fn append_raw_array(
+        &self,
+        values: &mut dyn Iterator<Item = std::io::Result<Box<RawValue>>>,
+    ) -> std::io::Result<()> {
+        let mut guard = self.start_record()?;
+        write_raw_array(&mut *guard, self.pretty, values)?;
+        writeln!(&mut *guard)
     }
 }
 
@@ -31,33 +77,87 @@ impl Writeable for FileOutput {
         self.pretty = pretty;
     }
 
+    fn set_compression(
+        &mut self,
+        compression: Option<Compression>,
+        level: Option<u32>,
+    ) -> std::io::Result<()> {
+        let compression = compression.or(Compression::from_extension(&self.path));
+        // Nothing has been written yet, so the file is simply opened again with the new encoder
+        self.writer = Arc::new(Mutex::new(open(&self.path, compression, level)?));
+        Ok(())
+    }
+
+    fn write_entry(&self, key: String, value: Value) -> std::io::Result<()> {
+        log::debug!("Writing entry {key} to {}", self.path.display());
+        write_entry_line(&mut *self.lock(), self.pretty, key, value)
+    }
+
     fn write_entries(&self, entries: Vec<(String, Value)>) -> std::io::Result<()> {
-        let mut guard = self.writer.lock().expect("Failed to get writer lock");
+        let mut guard = self.lock();
+        for (key, value) in entries {
+            write_entry_line(&mut *guard, self.pretty, key, value)?;
+        }
+        Ok(())
+    }
+
+    fn write_raw_entry(&self, key: String, value: Box<RawValue>) -> std::io::Result<()> {
+        log::debug!("Writing entry {key} to {}", self.path.display());
+        write_entry_line(&mut *self.lock(), self.pretty, key, value)
+    }
+
+    fn write_raw_entries(&self, entries: Vec<(String, Box<RawValue>)>) -> std::io::Result<()> {
+        let mut guard = self.lock();
         for (key, value) in entries {
-            let entry = serde_json::json!({key: value});
-            match self.pretty {
-                true => serde_json::to_writer_pretty(&mut *guard, &entry)?,
-                false => serde_json::to_writer(&mut *guard, &entry)?,
-            }
-            writeln!(&mut *guard)?;
+            write_entry_line(&mut *guard, self.pretty, key, value)?;
         }
         Ok(())
     }
+
+    /// Also ends the compressed stream, if any, so nothing can be written afterwards.
+    fn flush(&self) -> std::io::Result<()> {
+        self.lock().finish()
+    }
 }
 
 impl FileOutput {
+    /// Opens `path` for writing, compressed if its extension names a compression format.
     pub fn new(path: PathBuf, pretty: bool) -> Self {
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&path)
-            .expect("Failed to open file");
-        let writer = Arc::new(Mutex::new(BufWriter::new(file)));
+        let writer =
+            open(&path, Compression::from_extension(&path), None).expect("Failed to open file");
+        let writer = Arc::new(Mutex::new(writer));
         Self {
             pretty,
+            json_seq: false,
             writer,
             path,
         }
     }
+
+    fn lock(&self) -> MutexGuard<'_, Encoder<BufWriter<File>>> {
+        self.writer.lock().expect("Failed to get writer lock")
+    }
+
+    /// Locks the writer to append a record, first writing the record separator when the output
+    /// is a JSON text sequence.
+    fn start_record(&self) -> std::io::Result<MutexGuard<'_, Encoder<BufWriter<File>>>> {
+        let mut guard = self.lock();
+        if self.json_seq {
+            guard.write_all(&[RECORD_SEPARATOR as u8])?;
+        }
+        Ok(guard)
+    }
+}
+
+fn open(
+    path: &Path,
+    compression: Option<Compression>,
+    level: Option<u32>,
+) -> std::io::Result<Encoder<BufWriter<File>>> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    Encoder::new(BufWriter::new(file), compression, level)
 }
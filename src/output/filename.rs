@@ -0,0 +1,174 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::PathBuf,
+};
+
+/// The longest file name most filesystems accept, in bytes
+const MAX_NAME_BYTES: usize = 255;
+/// The longest extension, such as `.json.gz`, kept when a name is shortened
+const MAX_EXTENSION_BYTES: usize = 16;
+
+/// Turns a filename built from record content into a path that stays inside the output directory.
+/// A `/` still starts a subdirectory, but an absolute path is made relative, a component made of
+/// dots has them replaced by `_`, as do backslashes and control characters, and an over-long
+/// component is shortened. When `strict`, any of these is an error instead.
+pub fn sanitize(filename: &str, strict: bool) -> std::io::Result<PathBuf> {
+    let mut path = PathBuf::new();
+    let mut reasons = Vec::new();
+    for component in filename.split('/') {
+        let mut reason = |reason: &'static str| {
+            if !reasons.contains(&reason) {
+                reasons.push(reason);
+            }
+        };
+        if component.is_empty() {
+            reason("is an absolute path or has an empty path component");
+            continue;
+        }
+        let mut cleaned: String = match component.bytes().all(|byte| byte == b'.') {
+            true => {
+                reason("has a `.` or `..` path component");
+                "_".repeat(component.len())
+            }
+            false => component
+                .chars()
+                .map(|c| match c {
+                    '\\' => {
+                        reason("holds a backslash");
+                        '_'
+                    }
+                    c if c.is_control() => {
+                        reason("holds a NUL or control character");
+                        '_'
+                    }
+                    c => c,
+                })
+                .collect(),
+        };
+        if cleaned.len() > MAX_NAME_BYTES {
+            reason("has a component longer than 255 bytes");
+            cleaned = shorten(&cleaned);
+        }
+        path.push(cleaned);
+    }
+    if path.as_os_str().is_empty() {
+        path.push("_");
+    }
+    if reasons.is_empty() {
+        return Ok(path);
+    }
+    let reasons = reasons.join(" and ");
+    if strict {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Refusing to write {filename:?}, it {reasons}"),
+        ));
+    }
+    log::warn!("Writing {filename:?} as {}, it {reasons}", path.display());
+    Ok(path)
+}
+
+/// Escapes a name read from record content, such as a key or a field value, for use as a single
+/// path component, so that only templates and restored paths create subdirectories. Each `/` is
+/// replaced by `_`. When `strict`, a name holding one is an error instead.
+pub fn escape_component(name: &str, strict: bool) -> std::io::Result<String> {
+    if !name.contains('/') {
+        return Ok(name.to_string());
+    }
+    if strict {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Refusing to write {name:?}, it holds a `/`"),
+        ));
+    }
+    let escaped = name.replace('/', "_");
+    log::warn!("Writing {name:?} as {escaped:?}, it holds a `/`");
+    Ok(escaped)
+}
+
+/// Cuts `name` down to `MAX_NAME_BYTES`, keeping its extension and ending the rest with a hash of
+/// the whole name, so that long names sharing a beginning stay distinct.
+fn shorten(name: &str) -> String {
+    let extension = name
+        .rfind(".json")
+        .map(|i| &name[i..])
+        .filter(|extension| extension.len() <= MAX_EXTENSION_BYTES)
+        .unwrap_or_default();
+    let hash = format!("-{:016x}", fnv1a(name.as_bytes()));
+    let mut keep = MAX_NAME_BYTES - hash.len() - extension.len();
+    while !name.is_char_boundary(keep) {
+        keep -= 1;
+    }
+    format!("{}{hash}{extension}", &name[..keep])
+}
+
+/// The 64-bit FNV-1a hash of `bytes`, which unlike the standard library's hashers is the same
+/// in every build, so a shortened name does not change between releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_rewrites_unsafe_names() {
+        let cases = [
+            ("a/b.json", "a/b.json"),
+            ("../../etc/cron.d/x.json", "__/__/etc/cron.d/x.json"),
+            ("/tmp/evil.json", "tmp/evil.json"),
+            ("a/./b//c.json", "a/_/b/c.json"),
+            ("a\0b\n\\c.json", "a_b__c.json"),
+            ("..", "__"),
+            ("", "_"),
+        ];
+        for (filename, expected) in cases {
+            assert_eq!(sanitize(filename, false).unwrap(), PathBuf::from(expected));
+        }
+        for (filename, expected) in cases.iter().skip(1) {
+            assert!(
+                sanitize(filename, true).is_err(),
+                "{filename} -> {expected}"
+            );
+        }
+        assert!(sanitize("a/b.json", true).is_ok());
+    }
+
+    #[test]
+    fn test_sanitize_shortens_long_names() {
+        let long = "é".repeat(200) + ".json.gz";
+        let shortened = sanitize(&long, false).unwrap();
+        let shortened = shortened.to_str().unwrap();
+        assert!(shortened.len() <= MAX_NAME_BYTES);
+        assert!(shortened.ends_with(".json.gz"));
+        assert_ne!(
+            shortened,
+            sanitize(&("é".repeat(201) + ".json.gz"), false)
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        assert!(sanitize(&long, true).is_err());
+    }
+
+    #[test]
+    fn test_shortened_names_are_stable() {
+        // The hash must not change between builds, or re-running a command would rename files
+        let shortened = sanitize(&("a".repeat(300) + ".json"), false).unwrap();
+        assert_eq!(
+            shortened.to_str().unwrap(),
+            "a".repeat(MAX_NAME_BYTES - 22) + "-0f7888131f08ff8b.json"
+        );
+    }
+
+    #[test]
+    fn test_escape_component() {
+        assert_eq!(escape_component("a.b", false).unwrap(), "a.b");
+        assert_eq!(escape_component("../a/b", false).unwrap(), ".._a_b");
+        assert!(escape_component("a/b", true).is_err());
+        assert!(escape_component("a", true).is_ok());
+    }
+}
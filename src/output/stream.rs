@@ -1,26 +1,103 @@
-use super::{Appendable, Writeable};
-use serde_json::Value;
-use std::io::stdout;
+use super::{
+    write_array, write_entry_line, write_line, write_object, write_raw_array, write_raw_object,
+    Appendable, Writeable,
+};
+use crate::{
+    compression::{Compression, Encoder},
+    input::RECORD_SEPARATOR,
+};
+use serde_json::{value::RawValue, Value};
+use std::{
+    io::{stdout, BufWriter, Stdout, Write},
+    sync::{Arc, Mutex, MutexGuard},
+};
 
-#[derive(Clone, Debug)]
+/// Size of the stdout buffer, large enough that piping millions of small records does not turn
+/// into one write per line.
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// Writes to stdout through a single buffer, flushed by `Writeable::flush` when the command ends.
+/// The mutex plays the part of the stdout lock, since `StdoutLock` cannot be shared across the
+/// threads writing entries.
+#[derive(Clone)]
 pub struct StreamOutput {
     pretty: bool,
+    json_seq: bool,
+    writer: Arc<Mutex<Encoder<BufWriter<Stdout>>>>,
 }
 
 impl StreamOutput {
     pub fn new(pretty: bool) -> Self {
-        Self { pretty }
+        let writer = Encoder::Plain(BufWriter::with_capacity(BUFFER_SIZE, stdout()));
+        Self {
+            pretty,
+            json_seq: false,
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Encoder<BufWriter<Stdout>>> {
+        self.writer.lock().expect("Failed to get writer lock")
+    }
+
+    /// Locks the writer to append a record, first writing the record separator when the output
+    /// is a JSON text sequence.
+    fn start_record(&self) -> std::io::Result<MutexGuard<'_, Encoder<BufWriter<Stdout>>>> {
+        let mut guard = self.lock();
+        if self.json_seq {
+            guard.write_all(&[RECORD_SEPARATOR as u8])?;
+        }
+        Ok(guard)
     }
 }
 
 impl Appendable for StreamOutput {
+    fn set_json_seq(&mut self, json_seq: bool) {
+        self.json_seq = json_seq;
+    }
+
     fn append(&self, content: Value) -> std::io::Result<()> {
-        match self.pretty {
-            true => serde_json::to_writer_pretty(stdout(), &content)?,
-            false => serde_json::to_writer(stdout(), &content)?,
-        }
-        println!();
-        Ok(())
+        write_line(&mut *self.start_record()?, self.pretty, &content)
+    }
+
+    fn append_object(
+        &self,
+        entries: &mut dyn Iterator<Item = std::io::Result<(String, Value)>>,
+    ) -> std::io::Result<()> {
+        let mut guard = self.start_record()?;
+        write_object(&mut *guard, self.pretty, entries)?;
+        writeln!(&mut *guard)
+    }
+
+    fn append_raw(&self, content: Box<RawValue>) -> std::io::Result<()> {
+        write_line(&mut *self.start_record()?, false, &content)
+    }
+
+    fn append_raw_object(
+        &self,
+        entries: &mut dyn Iterator<Item = std::io::Result<(String, Box<RawValue>)>>,
+    ) -> std::io::Result<()> {
+        let mut guard = self.start_record()?;
+        write_raw_object(&mut *guard, self.pretty, entries)?;
+        writeln!(&mut *guard)
+    }
+
+    fn append_array(
+        &self,
+        values: &mut dyn Iterator<Item = std::io::Result<Value>>,
+    ) -> std::io::Result<()> {
+        let mut guard = self.start_record()?;
+        write_array(&mut *guard, self.pretty, values)?;
+        writeln!(&mut *guard)
+    }
+
+    fn append_raw_array(
+        &self,
+        values: &mut dyn Iterator<Item = std::io::Result<Box<RawValue>>>,
+    ) -> std::io::Result<()> {
+        let mut guard = self.start_record()?;
+        write_raw_array(&mut *guard, self.pretty, values)?;
+        writeln!(&mut *guard)
     }
 }
 
@@ -29,15 +106,42 @@ impl Writeable for StreamOutput {
         self.pretty = pretty;
     }
 
-    fn write_entries(&self, mut entries: Vec<(String, Value)>) -> std::io::Result<()> {
-        for (key, value) in entries.drain(..) {
-            let entry = serde_json::json!({key: value});
-            match self.pretty {
-                true => serde_json::to_writer_pretty(stdout(), &entry)?,
-                false => serde_json::to_writer(stdout(), &entry)?,
-            }
-            println!();
+    fn set_compression(
+        &mut self,
+        compression: Option<Compression>,
+        level: Option<u32>,
+    ) -> std::io::Result<()> {
+        let writer = BufWriter::with_capacity(BUFFER_SIZE, stdout());
+        self.writer = Arc::new(Mutex::new(Encoder::new(writer, compression, level)?));
+        Ok(())
+    }
+
+    fn write_entry(&self, key: String, value: Value) -> std::io::Result<()> {
+        write_entry_line(&mut *self.lock(), self.pretty, key, value)
+    }
+
+    fn write_entries(&self, entries: Vec<(String, Value)>) -> std::io::Result<()> {
+        let mut guard = self.lock();
+        for (key, value) in entries {
+            write_entry_line(&mut *guard, self.pretty, key, value)?;
         }
         Ok(())
     }
+
+    fn write_raw_entry(&self, key: String, value: Box<RawValue>) -> std::io::Result<()> {
+        write_entry_line(&mut *self.lock(), self.pretty, key, value)
+    }
+
+    fn write_raw_entries(&self, entries: Vec<(String, Box<RawValue>)>) -> std::io::Result<()> {
+        let mut guard = self.lock();
+        for (key, value) in entries {
+            write_entry_line(&mut *guard, self.pretty, key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Also ends the compressed stream, if any, so nothing can be written afterwards.
+    fn flush(&self) -> std::io::Result<()> {
+        self.lock().finish()
+    }
 }
@@ -4,5 +4,7 @@ pub mod json;
 pub mod json_field;
 /// Process newline-delimited lists of JSON objects
 mod ndjson;
+/// Name output files from the fields of each record
+pub mod template;
 
-pub use ndjson::{NdjsonBundler, NdjsonUnbundler};
+pub use ndjson::{FilenameField, NdjsonBundler, NdjsonUnbundler};
@@ -1,101 +1,442 @@
-use rayon::prelude::*;
+use crate::{
+    input::{stream_entries, Entries, EntryKey, JsonReaderInput, JsonSourceInput, Order},
+    output::{JsonAppendableOutput, JsonWritableOutput, Writeable},
+};
+use eyre::{eyre, Report, Result};
 use regex::Regex;
-use serde_json::Value;
-use std::collections::HashMap;
+use serde_json::{value::RawValue, Value};
+use std::collections::HashSet;
 
-/// Merges a vector of entries into a single JSON object, optionally filtering by a regex pattern.
+/// How `stream_merge` reads and writes the entries of its inputs
+#[derive(Clone, Debug, Default)]
+pub struct MergeOptions {
+    /// A regex pattern to filter the keys of the entries.
+    pub filter: Option<String>,
+    /// Whether to read the entries in alphabetical key order.
+    pub sort: bool,
+    /// Whether to copy each value byte for byte instead of re-serializing it.
+    pub raw: bool,
+    /// Whether to write the values as one array, ordered by key, instead of an object. Keys that
+    /// are plain indexes, as `split` names array elements, are ordered numerically.
+    pub array: bool,
+    /// A field to add to each object value, holding the input it was read from.
+    pub tag: Option<String>,
+    /// Whether reading a key twice is an error, rather than only the first one being kept.
+    pub unique_keys: bool,
+}
+
+/// Merges the entries of `inputs` into a single JSON object, writing each key to `output` as soon
+/// as its value has been read instead of building the merged object in memory. When several inputs
+/// hold the same key, the first input given takes precedence.
 ///
 /// # Arguments
 ///
-/// * `entries` - A vector of tuples where each tuple contains a key (String) and a value (Value).
-/// * `filter` - An optional string that represents a regex pattern to filter the keys of the entries.
-///
-/// # Returns
-///
-/// A `Value` representing a JSON object containing the merged entries that match the filter,
-/// or all entries if no filter is provided.
-
-pub fn merge(entries: Vec<(String, Value)>, filter: Option<String>) -> Value {
-    if filter.is_none() {
-        log::debug!("No key filter given");
-        return entries.into_iter().collect();
+/// * `inputs` - The `JsonSourceInput`s to read entries from, in order of precedence.
+/// * `output` - The `JsonAppendableOutput` the merged object is written to.
+/// * `options` - How the entries are filtered, ordered and written.
+pub fn stream_merge(
+    inputs: &[JsonSourceInput],
+    output: &JsonAppendableOutput,
+    options: MergeOptions,
+) -> Result<()> {
+    let MergeOptions {
+        filter,
+        sort,
+        raw,
+        array,
+        tag,
+        unique_keys,
+    } = options;
+    let regex = key_filter(filter);
+    let order = match (sort, array) {
+        (_, true) => Order::Indexed,
+        (true, false) => Order::Sorted,
+        (false, false) => Order::Listed,
+    };
+    let sources = inputs
+        .iter()
+        .map(|input| {
+            let source = input
+                .read()
+                .map_err(|_| eyre!("Error acquiring read lock on input"))?;
+            Ok((input.name(), source))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let output = output
+        .read()
+        .map_err(|_| eyre!("Error acquiring read lock on output"))?;
+    let mut error = None;
+    let written = if raw {
+        let sources = sources
+            .iter()
+            .map(|(name, source)| Ok((*name, source.raw_entries(order)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut entries = filtered(
+            merged_entries(sources, order, unique_keys),
+            &regex,
+            &mut error,
+        );
+        match array {
+            true => output.append_raw_array(&mut entries.map(|entry| Ok(entry?.1))),
+            false => output.append_raw_object(&mut entries),
+        }
     } else {
-        let regex: Option<Regex> = filter.and_then(|f| Regex::new(&f).ok());
-        log::info!("Regex key filter: {:?}", regex);
-        let includes_key = |(key, _): &(String, Value)| match regex {
-            Some(ref regex) => regex.is_match(key),
-            None => true,
+        let sources = sources
+            .iter()
+            .map(|(name, source)| {
+                let entries = source.entries(order)?;
+                let entries: Entries<'_> = match &tag {
+                    Some(field) => Box::new(entries.map(move |entry| {
+                        entry.map(|(key, mut value)| {
+                            tag_source(&mut value, field, name);
+                            (key, value)
+                        })
+                    })),
+                    None => entries,
+                };
+                Ok((*name, entries))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut entries = filtered(
+            merged_entries(sources, order, unique_keys),
+            &regex,
+            &mut error,
+        );
+        match array {
+            true => output.append_array(&mut entries.map(|entry| Ok(entry?.1))),
+            false => output.append_object(&mut entries),
+        }
+    };
+    // The error of an entry that could not be read is passed through the output as an I/O error
+    match error {
+        Some(e) => Err(e),
+        None => Ok(written?),
+    }
+}
+
+/// Reads the entries of several named inputs as one sequence, skipping any entry whose key was
+/// already read, or failing on it when `unique`. When `order` sorts, the sorted entries of every
+/// input are interleaved by key, otherwise each input is read in turn.
+fn merged_entries<'a, V: 'a>(
+    sources: Vec<(&'a str, Entries<'a, V>)>,
+    order: Order,
+    unique: bool,
+) -> Entries<'a, V> {
+    let mut sources: Vec<_> = sources
+        .into_iter()
+        .map(|(name, entries)| (name, entries.peekable()))
+        .collect();
+    let mut seen = HashSet::new();
+    let mut current = 0;
+    Box::new(std::iter::from_fn(move || loop {
+        let next = match order.is_sorted() {
+            // Errors come first, then the smallest key, taken from the earliest input on a tie
+            true => sources
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, (_, entries))| {
+                    let key = entries.peek()?.as_ref().ok().map(|(key, _)| order.key(key));
+                    Some((key, i))
+                })
+                .min()
+                .map(|(_, i)| i),
+            false => {
+                while sources.get_mut(current)?.1.peek().is_none() {
+                    current += 1;
+                }
+                Some(current)
+            }
         };
-        entries.into_iter().filter(includes_key).collect::<Value>()
+        let (name, entries) = &mut sources[next?];
+        match entries.next()? {
+            Ok((key, _)) if !seen.insert(key.clone()) => match unique {
+                true => return Some(Err(eyre!("Duplicate key {key} from {name}"))),
+                false => log::warn!(
+                    "Skipping duplicate key {key} from {name}, the first one read is kept"
+                ),
+            },
+            entry => return Some(entry),
+        }
+    }))
+}
+
+/// Adds `field` to an object, naming the input it was read from. Other values cannot hold a
+/// field, so they are left as they are.
+pub fn tag_source(json: &mut Value, field: &str, source: &str) {
+    match json {
+        Value::Object(object) => {
+            object.insert(field.to_string(), Value::from(source));
+        }
+        _ => log::warn!("Cannot tag a value from {source} that is not an object"),
     }
 }
 
-/// Splits a HashMap of entries into a vector of tuples, filtering by a regex pattern if provided.
+/// Yields the entries whose key matches `regex`. An entry that could not be read is stored in
+/// `error` and yielded as an I/O error, which stops the output before the merged value is closed.
+fn filtered<'a, V: 'a>(
+    entries: Entries<'a, V>,
+    regex: &'a Option<Regex>,
+    error: &'a mut Option<Report>,
+) -> impl Iterator<Item = std::io::Result<(String, V)>> + 'a {
+    entries
+        .filter(move |entry| match (entry, regex) {
+            (Ok((key, _)), Some(regex)) => regex.is_match(key),
+            _ => true,
+        })
+        .map(|entry| {
+            entry.map_err(|e| {
+                let message = e.to_string();
+                *error = Some(e);
+                std::io::Error::other(message)
+            })
+        })
+}
+
+/// Splits the top-level object or array of `input` into entries, writing each one to `output` as
+/// soon as it is parsed instead of loading the whole document first.
 ///
 /// # Arguments
 ///
-/// * `entries` - A HashMap where each key is a String and each value is a Value.
+/// * `input` - The `JsonReaderInput` holding a single top-level JSON object or array.
+/// * `output` - The `JsonWritableOutput` each matching entry is written to.
 /// * `filter` - An optional string that represents a regex pattern to filter the keys of the entries.
-///
-/// # Returns
-///
-/// A vector of tuples where each tuple contains a key (String) and a value (Value) that match the filter,
-/// or all entries if no filter is provided.
+/// * `raw` - Whether to copy each value byte for byte instead of re-serializing it.
+/// * `name` - Fields to name array elements by, using the first one found in each element.
+///   Elements are named by their zero-padded index otherwise.
+/// * `depth` - How many levels of nested objects to split further, naming their entries by
+///   their path like `a/b`, so a directory output writes them into subdirectories.
+pub fn stream_split(
+    input: &JsonReaderInput,
+    output: &JsonWritableOutput,
+    filter: Option<String>,
+    raw: bool,
+    name: Option<Vec<String>>,
+    depth: usize,
+) -> Result<()> {
+    let names: Vec<String> = name
+        .unwrap_or_default()
+        .iter()
+        .map(|name| dots_to_slashes(name))
+        .collect();
+    let regex = key_filter(filter);
+    let include = |key: &str| match regex {
+        Some(ref regex) => regex.is_match(key),
+        None => true,
+    };
+    let input = input
+        .read()
+        .map_err(|_| eyre!("Error acquiring read lock on input"))?;
+    let output = output
+        .read()
+        .map_err(|_| eyre!("Error acquiring read lock on output"))?;
+    match raw {
+        true => input.for_each_raw_entry(&include, &mut |key, value| {
+            let key = entry_name(key, &names, || {
+                Ok(first_name(&serde_json::from_str(value.get())?, &names))
+            })?;
+            // Array elements only have a name to filter on once they have been parsed
+            if !include(&key) {
+                return Ok(());
+            }
+            write_raw_nested(&*output, output.escape_component(&key)?, value, depth)
+        }),
+        false => input.for_each_entry(&include, &mut |key, value| {
+            let key = entry_name(key, &names, || Ok(first_name(&value, &names)))?;
+            if !include(&key) {
+                return Ok(());
+            }
+            write_nested(&*output, output.escape_component(&key)?, value, depth)
+        }),
+    }
+}
+
+/// Writes `value` as the entry `key`. While `depth` levels remain, a non-empty object is split
+/// further instead, writing each of its entries as `key/child`, with any `/` in `child` escaped.
+fn write_nested(output: &dyn Writeable, key: String, value: Value, depth: usize) -> Result<()> {
+    match value {
+        Value::Object(object) if depth > 0 && !object.is_empty() => {
+            for (child, value) in object {
+                let child = output.escape_component(&child)?;
+                write_nested(output, format!("{key}/{child}"), value, depth - 1)?;
+            }
+            Ok(())
+        }
+        value => {
+            log::debug!("Splitting entry {key}");
+            output.write_entry(key, value).map_err(|e| eyre!(e))
+        }
+    }
+}
 
-pub fn split(mut entries: HashMap<String, Value>, filter: Option<String>) -> Vec<(String, Value)> {
-    let regex: Option<Regex> = filter.and_then(|f| match Regex::new(&f) {
+/// Like `write_nested`, but copies each value byte for byte.
+fn write_raw_nested(
+    output: &dyn Writeable,
+    key: String,
+    value: Box<RawValue>,
+    depth: usize,
+) -> Result<()> {
+    if depth > 0 && value.get().starts_with('{') {
+        let mut children = Vec::new();
+        stream_entries(value.get().as_bytes(), &|_| true, &mut |child, value| {
+            children.push((child, value));
+            Ok(())
+        })?;
+        if !children.is_empty() {
+            for (child, value) in children {
+                if let EntryKey::Key(child) = child {
+                    let child = output.escape_component(&child)?;
+                    write_raw_nested(output, format!("{key}/{child}"), value, depth - 1)?;
+                }
+            }
+            return Ok(());
+        }
+    }
+    log::debug!("Splitting raw entry {key}");
+    output.write_raw_entry(key, value).map_err(|e| eyre!(e))
+}
+
+/// Names an entry of the top-level value. Object entries keep their key, while array elements
+/// are named by `element_name` when `names` are given, or by their zero-padded index.
+fn entry_name(
+    key: EntryKey,
+    names: &[String],
+    element_name: impl FnOnce() -> Result<Option<String>>,
+) -> Result<String> {
+    match key {
+        EntryKey::Key(key) => Ok(key),
+        EntryKey::Index(i) if names.is_empty() => Ok(format!("{i:06}")),
+        EntryKey::Index(i) => Ok(element_name()?.unwrap_or(format!("{i:06}"))),
+    }
+}
+
+/// Reads the first of the JSON pointers in `names` that is present in `json`, as `unbundle
+/// --name` does. Values that are not strings give an empty name.
+pub fn first_name(json: &Value, names: &[String]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| json.pointer(name))
+        .map(|value| value.as_str().unwrap_or_default().to_string())
+}
+
+/// Converts a `.`-delimited field path into a JSON pointer.
+pub fn dots_to_slashes(str: &str) -> String {
+    "/".to_string() + &str.split('.').collect::<Vec<&str>>().join("/")
+}
+
+/// Removes the insignificant whitespace from a raw JSON value so it fits on a single line,
+/// leaving every other byte, including number formatting and string escapes, untouched.
+pub fn minify(raw: &RawValue) -> Box<RawValue> {
+    let mut minified = String::with_capacity(raw.get().len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in raw.get().chars() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if matches!(c, ' ' | '\t' | '\n' | '\r') {
+            continue;
+        }
+        minified.push(c);
+    }
+    RawValue::from_string(minified).expect("Minified JSON is no longer valid")
+}
+
+fn key_filter(filter: Option<String>) -> Option<Regex> {
+    filter.and_then(|f| match Regex::new(&f) {
         Ok(r) => Some(r),
         Err(e) => {
             log::error!("Error parsing regex: {}", e);
             None
         }
-    });
-    entries
-        .par_drain()
-        .filter_map(|(key, value)| match regex {
-            Some(ref regex) => regex.is_match(&key).then_some((key, value)),
-            None => Some((key, value)),
-        })
-        .collect()
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Merges files holding `entries` through a file output, returning what was written.
+    fn merged(entries: &[(&str, Value)], filter: Option<&str>) -> Value {
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        for (key, value) in entries {
+            fs::write(
+                input_dir.path().join(format!("{key}.json")),
+                value.to_string(),
+            )
+            .unwrap();
+        }
+        let inputs = [input_dir.path().to_str().unwrap().parse().unwrap()];
+        let output_file = output_dir.path().join("merged.json");
+        let output = output_file.to_str().unwrap().parse().unwrap();
+        let options = MergeOptions {
+            filter: filter.map(String::from),
+            sort: true,
+            ..Default::default()
+        };
+        stream_merge(&inputs, &output, options).unwrap();
+        output.flush().unwrap();
+        serde_json::from_str(&fs::read_to_string(output_file).unwrap()).unwrap()
+    }
+
+    /// Splits a file holding `document` through a file output, which writes each entry as an
+    /// object of its own, returning the entries in the order they were written.
+    fn split(document: &str, filter: Option<&str>) -> Vec<(String, Value)> {
+        let dir = tempdir().unwrap();
+        let input_file = dir.path().join("input.json");
+        fs::write(&input_file, document).unwrap();
+        let input = input_file.to_str().unwrap().parse().unwrap();
+        let output_file = dir.path().join("split.json");
+        let output = output_file.to_str().unwrap().parse().unwrap();
+        stream_split(&input, &output, filter.map(String::from), false, None, 0).unwrap();
+        output.flush().unwrap();
+        let written = fs::read_to_string(output_file).unwrap();
+        serde_json::Deserializer::from_str(&written)
+            .into_iter::<serde_json::Map<String, Value>>()
+            .flat_map(Result::unwrap)
+            .collect()
+    }
 
     #[test]
     fn test_merge_filtered() {
-        let entries = vec![
-            ("a".to_string(), Value::String("1".to_string())),
-            ("b".to_string(), Value::String("2".to_string())),
-            ("c".to_string(), Value::String("3".to_string())),
-        ];
-        let filter = Some("b".to_string());
-        let result = merge(entries, filter);
-        assert_eq!(result, json!({"b": "2"}));
+        let entries = [("a", json!("1")), ("b", json!("2")), ("c", json!("3"))];
+        assert_eq!(merged(&entries, Some("b")), json!({"b": "2"}));
     }
 
     #[test]
     fn test_merge_unfiltered() {
-        let entries = vec![
-            ("a".to_string(), Value::String("1".to_string())),
-            ("b".to_string(), Value::String("2".to_string())),
-            ("c".to_string(), Value::String("3".to_string())),
-        ];
-        let filter = None;
-        let result = merge(entries, filter);
-        assert_eq!(result, json!({"a": "1", "b": "2", "c": "3"}));
+        let entries = [("a", json!("1")), ("b", json!("2")), ("c", json!("3"))];
+        assert_eq!(
+            merged(&entries, None),
+            json!({"a": "1", "b": "2", "c": "3"})
+        );
+    }
+
+    #[test]
+    fn test_minify_keeps_value_bytes() {
+        let raw = RawValue::from_string(
+            "{\n  \"a b\": 1.50,\n  \"c\": [ \"\\u00e9\\\" x\" , 1e3 ]\n}".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            minify(&raw).get(),
+            "{\"a b\":1.50,\"c\":[\"\\u00e9\\\" x\",1e3]}"
+        );
     }
 
     #[test]
     fn test_split_filtered() {
-        let mut entries = HashMap::new();
-        entries.insert("a".to_string(), Value::String("1".to_string()));
-        entries.insert("b".to_string(), Value::String("2".to_string()));
-        entries.insert("c".to_string(), Value::String("3".to_string()));
-        let filter = Some("b".to_string());
-        let result = split(entries, filter);
+        let result = split(r#"{"a": "1", "b": "2", "c": "3"}"#, Some("b"));
         assert_eq!(
             result,
             vec![("b".to_string(), Value::String("2".to_string()))]
@@ -104,15 +445,7 @@ mod tests {
 
     #[test]
     fn test_split_unfiltered() {
-        let mut entries = HashMap::new();
-        entries.insert("a".to_string(), Value::String("1".to_string()));
-        entries.insert("b".to_string(), Value::String("2".to_string()));
-        entries.insert("c".to_string(), Value::String("3".to_string()));
-
-        let filter = None;
-        let mut result = split(entries, filter);
-        // Split's output is non-deterministic, so we sort it to compare
-        result.sort_unstable_by_key(|(key, _)| key.clone());
+        let result = split(r#"{"a": "1", "b": "2", "c": "3"}"#, None);
         assert_eq!(
             result,
             vec![
@@ -122,4 +455,11 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_split_keeps_document_order() {
+        let result = split(r#"{"foxtrot": 6, "alpha": 1, "delta": 4}"#, None);
+        let keys: Vec<String> = result.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["foxtrot", "alpha", "delta"]);
+    }
 }
@@ -1,59 +1,243 @@
-use super::json_field::JsonField;
+use super::{
+    json::{dots_to_slashes, first_name, minify, tag_source},
+    json_field::JsonField,
+    template::Template,
+};
 use crate::{
-    input::{InputDirectory, JsonReaderInput, JsonSource},
-    output::{JsonAppendableOutput, JsonWritableOutput},
+    compression::Compression,
+    input::{count_lines, JsonReader, JsonReaderInput, JsonSourceInput, RecordReader},
+    output::{JsonAppendableOutput, JsonWritableOutput, Writeable},
 };
 use eyre::{eyre, Result};
-use serde_json::Value;
+use rayon::prelude::*;
+use serde_json::{value::RawValue, Map, Value};
+use std::{
+    path::Path,
+    sync::mpsc::{sync_channel, SyncSender},
+};
+
+/// Number of records `NdjsonUnbundler` reads ahead and parses in parallel at a time
+const BATCH_SIZE: usize = 1024;
+/// Approximate size in bytes of each newline-aligned chunk of a memory-mapped input
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Derives the output name of an entry from its position in the input and contents
+type NameEntry<'a> = dyn Fn(Position, &Value) -> std::io::Result<String> + Sync + 'a;
+
+/// A parsed record, along with its original text when unbundling in raw mode and the path it was
+/// bundled from, when it kept one
+struct Record {
+    json: Value,
+    raw: Option<Box<RawValue>>,
+    path: Option<String>,
+}
+
+/// A record read from the input, paired with the line it starts on
+type RawRecord = (usize, serde_json::Result<Box<RawValue>>);
+
+/// A record read from the input, paired with its position
+type PositionedRecord = (Position, serde_json::Result<Box<RawValue>>);
+
+/// Where a record was read from the input
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    /// The number default names use, which is the line the record starts on, or one past the
+    /// record before when several start on the same line
+    number: usize,
+    /// How many records came before it, given to filename templates as `{#}`
+    index: usize,
+    /// The line the record starts on, counting from zero
+    line: usize,
+}
+
+/// A record's position in the input and the result of parsing it
+type Parsed = (Position, serde_json::Result<Record>);
+
+/// Hands out the positions of records in the order they are read
+#[derive(Default)]
+struct Numbering {
+    next: usize,
+    index: usize,
+}
+
+impl Numbering {
+    /// The position of the next record, which starts on `line`
+    fn position(&mut self, line: usize) -> Position {
+        let position = Position {
+            number: self.next.max(line),
+            index: self.index,
+            line,
+        };
+        self.next = position.number + 1;
+        self.index += 1;
+        position
+    }
+}
+
+/// Where a bundled record keeps the path of the file it was read from
+#[derive(Clone, Debug)]
+pub struct FilenameField {
+    /// The field holding the path
+    pub field: String,
+    /// A field to wrap the record in, next to the path, instead of adding the path to the record
+    pub envelope: Option<String>,
+}
+
+impl FilenameField {
+    /// Adds `path` to `json`, or wraps both in an envelope. Only objects can hold the path, so
+    /// other values are left as they are unless they are wrapped.
+    pub fn wrap(&self, mut json: Value, path: &str) -> Value {
+        match (&self.envelope, &mut json) {
+            (Some(envelope), _) => {
+                let mut wrapped = Map::new();
+                wrapped.insert(self.field.clone(), Value::from(path));
+                wrapped.insert(envelope.clone(), json);
+                Value::Object(wrapped)
+            }
+            (None, Value::Object(object)) => {
+                object.insert(self.field.clone(), Value::from(path));
+                json
+            }
+            (None, _) => {
+                log::warn!("Cannot add the path {path} to a record that is not an object");
+                json
+            }
+        }
+    }
+
+    /// Takes the path back out of a record written by `wrap`, returning the record as it was
+    /// read and the path, if it has one.
+    pub fn unwrap(&self, mut json: Value) -> (Value, Option<String>) {
+        let Value::Object(object) = &mut json else {
+            return (json, None);
+        };
+        let path = match object.get(&self.field) {
+            Some(Value::String(path)) => Some(path.clone()),
+            _ => None,
+        };
+        if let Some(envelope) = &self.envelope {
+            return match object.shift_remove(envelope) {
+                Some(record) => (record, path),
+                None => {
+                    log::warn!("Record has no {envelope} field, keeping it whole");
+                    (json, path)
+                }
+            };
+        }
+        if path.is_some() {
+            object.shift_remove(&self.field);
+        }
+        (json, path)
+    }
+}
 
 pub struct NdjsonBundler {
-    input: InputDirectory,
+    inputs: Vec<JsonSourceInput>,
     output: JsonAppendableOutput,
 }
 
 impl NdjsonBundler {
-    pub fn new(input: InputDirectory, output: JsonAppendableOutput) -> Self {
-        Self { input, output }
+    pub fn new(inputs: Vec<JsonSourceInput>, output: JsonAppendableOutput) -> Self {
+        Self { inputs, output }
     }
 
-    /// Bundles JSON files from the specified directory into a single output.
+    /// Bundles the JSON files of each input into a single output, one input after another.
     ///
     /// # Arguments
     ///
-    /// * `dir` - A reference to a `PathBuf` representing the directory containing JSON files.
-    /// * `output` - A reference to an `Output` where the bundled JSON will be written.
+    /// * `json_fields` - Nested JSON fields to escape into strings.
+    /// * `tag` - A field to add to each record, holding the input it was read from.
+    /// * `filename` - Where to keep the path of the file each record was read from.
+    pub fn bundle(
+        &self,
+        json_fields: Option<Vec<String>>,
+        tag: Option<String>,
+        filename: Option<FilenameField>,
+    ) -> Result<()> {
+        for input in &self.inputs {
+            self.read_entries_to_output(
+                input,
+                json_fields.as_deref(),
+                tag.as_deref(),
+                filename.as_ref(),
+            )?;
+        }
+        Ok(())
+    }
 
-    pub fn bundle(&self, json_fields: Option<Vec<String>>) -> Result<()> {
-        self.read_entries_to_output(json_fields)
+    /// Bundles JSON files without re-serializing them. Only the whitespace between tokens is
+    /// removed so each file fits on one line; every other byte is copied as it was written.
+    pub fn bundle_raw(&self) -> Result<()> {
+        let output = self
+            .output
+            .read()
+            .map_err(|_| eyre!("Error acquiring read lock on output"))?;
+        for input in &self.inputs {
+            let input = input
+                .read()
+                .map_err(|_| eyre!("Error acquiring read lock on input"))?;
+            input.raw_records()?.try_for_each(|entry| {
+                let (_name, raw) = entry?;
+                output.append_raw(minify(&raw)).map_err(|e| eyre!(e))
+            })?;
+        }
+        Ok(())
     }
 
-    /// Reads all JSON files in the specified directory and appends their contents to the output.
+    /// Reads all JSON files of `input` and appends their contents to the output as each one is
+    /// parsed.
     ///
     /// # Arguments
     ///
-    /// * `dir` - A reference to a `PathBuf` representing the directory containing JSON files.
-    /// * `output` - A reference to an `Output` where the JSON data will be appended.
-
-    fn read_entries_to_output(&self, json_fields: Option<Vec<String>>) -> Result<()> {
+    /// * `input` - The directory, file or stdin to read records from.
+    /// * `json_fields` - Nested JSON fields to escape into strings.
+    /// * `tag` - A field to add to each record, holding the name of `input`.
+    /// * `filename` - Where to keep the path of each file below `input`. Records read from stdin
+    ///   have no path, so they are passed through whole.
+    fn read_entries_to_output(
+        &self,
+        input: &JsonSourceInput,
+        json_fields: Option<&[String]>,
+        tag: Option<&str>,
+        filename: Option<&FilenameField>,
+    ) -> Result<()> {
         log::debug!("Escaping fields: {:?}", json_fields);
         let output = self
             .output
             .read()
             .map_err(|_| eyre!("Error acquiring read lock on output"))?;
-        self.input
-            .get_entries(false)
-            .drain(..)
-            .try_for_each(|(_name, mut json)| {
-                if let Some(ref json_fields) = json_fields {
-                    json_fields.iter().for_each(|field| {
-                        if let Some(value) = json.pointer_mut(&dots_to_slashes(field)) {
-                            log::debug!("Escaping field {}", field);
-                            *value = JsonField::from(value.clone()).escape();
-                        }
-                    });
-                }
-                output.append(json).map_err(|e| eyre!(e))
-            })
+        let name = input.name();
+        let filename = filename.filter(|_| name != "-");
+        let input = input
+            .read()
+            .map_err(|_| eyre!("Error acquiring read lock on input"))?;
+        input.records()?.try_for_each(|entry| {
+            let (path, mut json) = entry?;
+            if let Some(json_fields) = json_fields {
+                json_fields.iter().for_each(|field| {
+                    if let Some(value) = json.pointer_mut(&dots_to_slashes(field)) {
+                        log::debug!("Escaping field {}", field);
+                        *value = JsonField::from(value.clone()).escape();
+                    }
+                });
+            }
+            if let Some(field) = tag {
+                tag_source(&mut json, field, name);
+            }
+            if let Some(filename) = filename {
+                // The records are decompressed, so a compressed file is restored without its codec
+                let path = match Compression::from_extension(Path::new(&path)) {
+                    Some(_) => Path::new(&path)
+                        .with_extension("")
+                        .to_string_lossy()
+                        .to_string(),
+                    None => path,
+                };
+                json = filename.wrap(json, &path);
+            }
+            output.append(json).map_err(|e| eyre!(e))
+        })?;
+        Ok(())
     }
 }
 
@@ -61,6 +245,9 @@ pub struct NdjsonUnbundler {
     input: JsonReaderInput,
     output: JsonWritableOutput,
     unescape_fields: Option<Vec<String>>,
+    raw: bool,
+    filename: Option<FilenameField>,
+    template: Option<Template>,
 }
 
 impl NdjsonUnbundler {
@@ -68,83 +255,413 @@ impl NdjsonUnbundler {
         input: JsonReaderInput,
         output: JsonWritableOutput,
         unescape_fields: Option<Vec<String>>,
+        raw: bool,
     ) -> Self {
         Self {
             input,
             output,
             unescape_fields,
+            raw,
+            filename: None,
+            template: None,
         }
     }
 
+    /// Names each record by the path it was bundled from, as `bundle` kept it in `filename`, and
+    /// takes the path back out of the record. Records without a path are named as usual.
+    pub fn set_filename_field(&mut self, filename: Option<FilenameField>) {
+        self.filename = filename;
+    }
+
+    /// Names each record by filling in `template`, in place of the name and type fields.
+    pub fn set_template(&mut self, template: Option<Template>) {
+        self.template = template;
+    }
+
     /// Unbundles NDJSON file and writes separate JSON files to the specified output.
     ///
+    /// Lines are parsed in parallel batches, but entries are numbered and written in input order.
+    ///
     /// # Arguments
     ///
     /// * `input` - A refeence to an `Input` representing the source of NDJSON data.
     /// * `output` - A reference to an `Output` where the JSON files will be written.
     /// * `name` - An optional name for the JSON objects, used as a key to extract values.
-
     pub fn unbundle(&self, name: Option<Vec<String>>, type_field: Option<String>) -> Result<()> {
-        let mut i: usize = 0;
         let name_list = match name {
             Some(list) => list
                 .iter()
-                .map(|name| dots_to_slashes(&name))
+                .map(|name| dots_to_slashes(name))
                 .collect::<Vec<String>>(),
             None => vec![],
         };
         let type_field = type_field.map(|field| dots_to_slashes(&field));
+        // In raw mode lines are only parsed into values when a name has to be read from them
+        let named = !name_list.is_empty() || type_field.is_some() || self.template.is_some();
 
-        let name_entry = |i: usize, json: &Value| {
-            let default_name = format!("object-{i:06}");
+        let output = self
+            .output
+            .read()
+            .map_err(|_| eyre!("Error acquiring read lock on output"))?;
 
-            let name = name_list
-                .iter()
-                .find_map(|name| json.pointer(name))
-                .map_or(default_name, |value| {
-                    value.as_str().unwrap_or_default().to_string()
-                });
+        // Values read from a record are escaped, so they cannot start a subdirectory
+        let escape = |value: &str| output.escape_component(value);
+        let name_entry = |position: Position, json: &Value| {
+            let default_name = format!("object-{:06}", position.number);
 
-            match &type_field {
-                Some(field) => json.pointer(&field).map_or(name.clone(), |value| {
-                    format!("{name}.{}", value.as_str().unwrap_or_default().to_string())
-                }),
-                None => name,
+            if let Some(template) = &self.template {
+                return Ok(template
+                    .render(position.index, json, &escape)?
+                    .unwrap_or_else(|| {
+                        log::warn!(
+                            "Record starting on line {} does not fit the filename template, \
+                             naming it {default_name}",
+                            position.line
+                        );
+                        default_name
+                    }));
+            }
+
+            let name = match first_name(json, &name_list) {
+                Some(name) => escape(&name)?,
+                None => default_name,
+            };
+
+            match type_field.as_ref().and_then(|field| json.pointer(field)) {
+                Some(value) => Ok(format!(
+                    "{name}.{}",
+                    escape(value.as_str().unwrap_or_default())?
+                )),
+                None => Ok(name),
             }
         };
 
-        let mut buf = String::new();
-        while let Ok(()) = self.input.read_line(&mut buf) {
-            match serde_json::from_str::<Value>(&buf) {
-                Ok(mut json) => {
-                    self.unescape_fields(&mut json);
-                    let entry = vec![(name_entry(i, &json), json)];
-                    self.output
-                        .read()
-                        .map_err(|_| eyre!("Error acquiring read lock on output"))?
-                        .write_entries(entry)?
-                }
-                Err(e) if serde_json::Error::is_eof(&e) => break,
-                Err(e) => log::error!("Failed to parse line {}: {}", i, e),
+        let input = self
+            .input
+            .read()
+            .map_err(|_| eyre!("Error acquiring read lock on input"))?;
+
+        match input.mapped() {
+            Some(bytes) => self.unbundle_mapped(bytes, named, &name_entry, &*output),
+            None => self.unbundle_records(&*input, named, &name_entry, &*output),
+        }
+    }
+
+    /// Splits a memory-mapped input into newline-aligned chunks and parses the lines of each chunk
+    /// on its own rayon worker, as long as every line holds a whole record. A chunk that does not,
+    /// because its records are pretty-printed, concatenated or prefixed with record separators, or
+    /// because a line is broken, is read with a `RecordReader` instead, and chunking resumes at the
+    /// first line boundary the reader reaches past the end of that chunk.
+    fn unbundle_mapped(
+        &self,
+        bytes: &[u8],
+        named: bool,
+        name_entry: &NameEntry<'_>,
+        output: &dyn Writeable,
+    ) -> Result<()> {
+        let threads = rayon::current_num_threads();
+        let mut numbering = Numbering::default();
+        let mut start: usize = 0;
+        let mut line: usize = 0;
+        while start < bytes.len() {
+            let chunks: Vec<&[u8]> = aligned_chunks(&bytes[start..], CHUNK_SIZE)
+                .take(threads)
+                .collect();
+            let parsed: Vec<Option<Vec<(usize, Record)>>> = chunks
+                .par_iter()
+                .map(|chunk| self.parse_lines(chunk, named))
+                .collect();
+            let mut batch = Vec::new();
+            for (chunk, records) in chunks.into_iter().zip(parsed) {
+                let Some(records) = records else {
+                    write_parsed(std::mem::take(&mut batch), name_entry, output)?;
+                    let rest = &bytes[start..];
+                    let read =
+                        read_framed(rest, chunk.len(), line, &mut numbering, &mut |batch| {
+                            self.write_records(batch, named, name_entry, output)
+                        })?;
+                    line += count_lines(&rest[..read]);
+                    start += read;
+                    break;
+                };
+                batch.extend(
+                    records
+                        .into_iter()
+                        .map(|(n, record)| (numbering.position(line + n), Ok(record))),
+                );
+                line += count_lines(chunk);
+                start += chunk.len();
+            }
+            if !batch.is_empty() {
+                write_parsed(batch, name_entry, output)?;
             }
-            buf.clear();
-            i += 1;
         }
         Ok(())
     }
 
+    /// Parses each line of `chunk` as a record, returning them with the line they are on within
+    /// the chunk, or `None` if any line that is not blank fails to parse.
+    fn parse_lines(&self, chunk: &[u8], named: bool) -> Option<Vec<(usize, Record)>> {
+        chunk_lines(chunk)
+            .enumerate()
+            .filter(|(_, line)| !line.trim_ascii().is_empty())
+            .map(|(n, line)| Some((n, self.parse_line(line, named).ok()?)))
+            .collect()
+    }
+
+    /// Reads records on a separate thread while the previous batch is parsed and written, handing
+    /// each batch to the output in input order, along with the position of each record.
+    fn unbundle_records(
+        &self,
+        input: &dyn JsonReader,
+        named: bool,
+        name_entry: &NameEntry<'_>,
+        output: &dyn Writeable,
+    ) -> Result<()> {
+        std::thread::scope(|scope| {
+            let (sender, receiver) = sync_channel::<Vec<RawRecord>>(2);
+            let reader = scope.spawn(move || read_batches(input, sender));
+
+            let mut numbering = Numbering::default();
+            let written = receiver.into_iter().try_for_each(|batch| {
+                let positioned = batch
+                    .into_iter()
+                    .map(|(line, record)| (numbering.position(line), record))
+                    .collect();
+                self.write_records(positioned, named, name_entry, output)
+            });
+            // Once writing fails the receiver is dropped, which stops the reader
+            let read = reader
+                .join()
+                .map_err(|_| eyre!("Error reading input: reader thread panicked"))?;
+            written.and(read)
+        })
+    }
+
+    /// Parses a batch of records in parallel and writes them.
+    fn write_records(
+        &self,
+        batch: Vec<PositionedRecord>,
+        named: bool,
+        name_entry: &NameEntry<'_>,
+        output: &dyn Writeable,
+    ) -> Result<()> {
+        let parsed: Vec<Parsed> = batch
+            .into_par_iter()
+            .map(|(position, record)| {
+                (
+                    position,
+                    record.and_then(|raw| self.parse_record(raw, named)),
+                )
+            })
+            .collect();
+        write_parsed(parsed, name_entry, output)
+    }
+
+    fn parse_line(&self, line: &[u8], named: bool) -> serde_json::Result<Record> {
+        match self.raw {
+            true => self.parse_record(serde_json::from_slice(line)?, named),
+            false => Ok(self.record(serde_json::from_slice(line)?)),
+        }
+    }
+
+    fn parse_record(&self, raw: Box<RawValue>, named: bool) -> serde_json::Result<Record> {
+        if self.raw {
+            let json = match named {
+                true => serde_json::from_str(raw.get())?,
+                false => Value::Null,
+            };
+            return Ok(Record {
+                json,
+                raw: Some(raw),
+                path: None,
+            });
+        }
+        Ok(self.record(serde_json::from_str(raw.get())?))
+    }
+
+    fn record(&self, json: Value) -> Record {
+        let (mut json, path) = match &self.filename {
+            Some(filename) => filename.unwrap(json),
+            None => (json, None),
+        };
+        self.unescape_fields(&mut json);
+        Record {
+            json,
+            raw: None,
+            path,
+        }
+    }
+
     fn unescape_fields(&self, json: &mut Value) {
-        self.unescape_fields.as_ref().map(|fields| {
+        if let Some(fields) = &self.unescape_fields {
             fields.iter().for_each(|field| {
-                json.pointer_mut(&dots_to_slashes(field)).map(|value| {
+                if let Some(value) = json.pointer_mut(&dots_to_slashes(field)) {
                     log::debug!("Unescaping field {}", field);
                     *value = JsonField::from(value.clone()).unescape();
-                });
+                }
             })
-        });
+        }
     }
 }
 
-fn dots_to_slashes(str: &str) -> String {
-    "/".to_string() + &str.split('.').collect::<Vec<&str>>().join("/")
+/// Names and writes one batch of parsed records, logging those that failed to parse. A record
+/// whose name is refused is logged too, and returned as an error once the rest are written.
+fn write_parsed(
+    parsed: Vec<Parsed>,
+    name_entry: &NameEntry<'_>,
+    output: &dyn Writeable,
+) -> Result<()> {
+    let mut entries = Vec::with_capacity(parsed.len());
+    let mut raw_entries = Vec::new();
+    let mut refused = None;
+    for (position, result) in parsed {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                log::error!(
+                    "Failed to parse record starting on line {}: {}",
+                    position.line,
+                    e
+                );
+                continue;
+            }
+        };
+        // A restored path is written as it is, other files get the `.json` extension
+        let name = match (&record.path, &record.raw) {
+            (Some(path), _) => Ok(path.clone()),
+            (None, Some(_)) => name_entry(position, &record.json),
+            (None, None) => name_entry(position, &record.json).map(|name| format!("{name}.json")),
+        };
+        let name = match name {
+            Ok(name) => name,
+            Err(e) => {
+                log::error!(
+                    "Error naming record starting on line {}: {e}",
+                    position.line
+                );
+                refused.get_or_insert(e);
+                continue;
+            }
+        };
+        match record.raw {
+            Some(raw) => raw_entries.push((name, raw)),
+            None => entries.push((name, record.json)),
+        }
+    }
+    match raw_entries.is_empty() {
+        true => output.write_files(entries)?,
+        false => output.write_raw_entries(raw_entries)?,
+    }
+    match refused {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Reads records from `bytes`, which start on `line`, with a `RecordReader` until it has read at
+/// least `end` bytes and stopped at the end of a line, handing them to `write` in batches of
+/// `BATCH_SIZE`. Returns how many bytes it read.
+fn read_framed(
+    bytes: &[u8],
+    end: usize,
+    line: usize,
+    numbering: &mut Numbering,
+    write: &mut dyn FnMut(Vec<PositionedRecord>) -> Result<()>,
+) -> Result<usize> {
+    let mut input = bytes;
+    let mut records = RecordReader::default();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let read = loop {
+        let Some((start, record)) = records.next_record(&mut input) else {
+            break bytes.len();
+        };
+        batch.push((numbering.position(line + start), record));
+        if batch.len() == BATCH_SIZE {
+            write(std::mem::take(&mut batch))?;
+        }
+        let read = bytes.len() - input.len();
+        if read >= end
+            && bytes[..read].ends_with(b"\n")
+            && records.buffered().trim_ascii().is_empty()
+        {
+            break read;
+        }
+    };
+    write(batch)?;
+    Ok(read)
+}
+
+/// Divides `bytes` into chunks of at least `size` bytes that each end just after a newline, except
+/// for the last chunk, which runs to the end of the input.
+fn aligned_chunks(bytes: &[u8], size: usize) -> impl Iterator<Item = &[u8]> {
+    let mut rest = bytes;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = match rest
+            .get(size..)
+            .and_then(|tail| tail.iter().position(|&byte| byte == b'\n'))
+        {
+            Some(n) => size + n + 1,
+            None => rest.len(),
+        };
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+/// Splits a chunk from `aligned_chunks` into its lines, without their `\n` terminators.
+fn chunk_lines(chunk: &[u8]) -> impl Iterator<Item = &[u8]> {
+    chunk
+        .strip_suffix(b"\n")
+        .unwrap_or(chunk)
+        .split(|&byte| byte == b'\n')
+}
+
+/// Reads records from `input` in batches of `BATCH_SIZE`, each paired with the line it starts on,
+/// until the end of input. Stops early once the receiving side has hung up, and returns an error
+/// if the input could not be read.
+fn read_batches(input: &dyn JsonReader, sender: SyncSender<Vec<RawRecord>>) -> Result<()> {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let hung_up = || eyre!("Stopped reading input after a failed write");
+    let read = input.for_each_record(&mut |line, record| {
+        batch.push((line, record));
+        if batch.len() == BATCH_SIZE {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+            sender.send(full).map_err(|_| hung_up())?;
+        }
+        Ok(())
+    });
+    if read.is_ok() && !batch.is_empty() {
+        sender.send(batch).map_err(|_| hung_up())?;
+    }
+    read
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aligned_chunks_end_on_newlines() {
+        let input = b"{\"a\":1}\n{\"b\":2}\n{\"c\":3}\n{\"d\":4}";
+        let chunks: Vec<&[u8]> = aligned_chunks(input, 10).collect();
+        assert_eq!(chunks.concat(), input.to_vec());
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.ends_with(b"\n"));
+        }
+    }
+
+    #[test]
+    fn test_chunk_lines_keep_line_numbers() {
+        let input = b"{\"a\":1}\n{\"b\":2}\n\n{\"d\":4}\n";
+        let lines: Vec<&[u8]> = aligned_chunks(input, 4).flat_map(chunk_lines).collect();
+        assert_eq!(
+            lines,
+            vec![&b"{\"a\":1}"[..], b"{\"b\":2}", b"", b"{\"d\":4}"]
+        );
+    }
 }
@@ -0,0 +1,264 @@
+use super::json::dots_to_slashes;
+use serde_json::Value;
+
+/// The width indexes are padded to when a template does not give one, as in `object-000001`
+const INDEX_WIDTH: usize = 6;
+
+/// A filename built from the fields of each record, such as `{type}/{attributes.title|slug}.json`.
+///
+/// Each `{...}` placeholder holds one or more alternatives separated by commas, the first of which
+/// with a value is used. An alternative is a `.`-delimited field path or a `"quoted"` literal.
+/// Filters follow after `|`: `lower`, `upper` and `slug`. The placeholder `{#}` is the record's
+/// index, padded to six digits, or to `{#:N}` digits. Literal braces are written `{{` and `}}`.
+#[derive(Clone, Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+    start: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Index(usize),
+    Value {
+        alternatives: Vec<Alternative>,
+        filters: Vec<Filter>,
+    },
+}
+
+#[derive(Clone, Debug)]
+enum Alternative {
+    /// A JSON pointer into the record
+    Field(String),
+    Literal(String),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Filter {
+    Lower,
+    Upper,
+    Slug,
+}
+
+impl Template {
+    /// Numbers the records from `start` instead of from their position in the input.
+    pub fn starting_at(self, start: usize) -> Self {
+        Self { start, ..self }
+    }
+
+    /// Fills in the template for the record `json` at `index`, or returns `None` if a
+    /// placeholder has no value. A trailing `.json` is left off, as outputs add it to every name.
+    /// Values read from the record are passed through `escape`, so that only the template itself
+    /// can start a subdirectory.
+    pub fn render(
+        &self,
+        index: usize,
+        json: &Value,
+        escape: &dyn Fn(&str) -> std::io::Result<String>,
+    ) -> std::io::Result<Option<String>> {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Index(width) => {
+                    name.push_str(&format!("{:0width$}", index + self.start, width = width))
+                }
+                Part::Value {
+                    alternatives,
+                    filters,
+                } => {
+                    let found = alternatives
+                        .iter()
+                        .find_map(|alternative| match alternative {
+                            Alternative::Field(pointer) => match json.pointer(pointer)? {
+                                Value::String(value) => Some((value.clone(), true)),
+                                Value::Number(value) => Some((value.to_string(), true)),
+                                Value::Bool(value) => Some((value.to_string(), true)),
+                                _ => None,
+                            },
+                            Alternative::Literal(value) => Some((value.clone(), false)),
+                        });
+                    let Some((value, from_record)) = found else {
+                        return Ok(None);
+                    };
+                    let value = filters
+                        .iter()
+                        .fold(value, |value, filter| filter.apply(&value));
+                    match from_record {
+                        true => name.push_str(&escape(&value)?),
+                        false => name.push_str(&value),
+                    }
+                }
+            }
+        }
+        Ok(Some(match name.strip_suffix(".json") {
+            Some(stem) => stem.to_string(),
+            None => name,
+        }))
+    }
+}
+
+impl Filter {
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Filter::Lower => value.to_lowercase(),
+            Filter::Upper => value.to_uppercase(),
+            Filter::Slug => slug(value),
+        }
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "lower" => Ok(Filter::Lower),
+            "upper" => Ok(Filter::Upper),
+            "slug" => Ok(Filter::Slug),
+            s => Err(format!("Unknown filter {s}, expected lower, upper or slug")),
+        }
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed placeholder in {template}"))?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(placeholder(&rest[..end])?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("Unmatched }} in {template}")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts, start: 0 })
+    }
+}
+
+/// Parses the inside of a `{...}` placeholder.
+fn placeholder(content: &str) -> Result<Part, String> {
+    let mut sections = content.split('|');
+    let value = sections.next().unwrap_or_default().trim();
+    let filters = sections
+        .map(str::parse)
+        .collect::<Result<Vec<Filter>, _>>()?;
+    if let Some(width) = value.strip_prefix('#') {
+        let width = match width.strip_prefix(':') {
+            Some(width) => width
+                .parse()
+                .map_err(|_| format!("Invalid index width {width}"))?,
+            None if width.is_empty() => INDEX_WIDTH,
+            None => return Err(format!("Invalid index placeholder {{{content}}}")),
+        };
+        return Ok(Part::Index(width));
+    }
+    let alternatives = value
+        .split(',')
+        .map(|alternative| {
+            let alternative = alternative.trim();
+            match alternative
+                .strip_prefix('"')
+                .and_then(|a| a.strip_suffix('"'))
+            {
+                Some(literal) => Ok(Alternative::Literal(literal.to_string())),
+                None if alternative.is_empty() => Err(format!("Empty placeholder {{{content}}}")),
+                None => Ok(Alternative::Field(dots_to_slashes(alternative))),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Part::Value {
+        alternatives,
+        filters,
+    })
+}
+
+/// Lowercases `value` and joins its runs of letters and digits with `-`, as in `my-dashboard-2`.
+fn slug(value: &str) -> String {
+    value
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(template: &Template, index: usize, record: &Value) -> Option<String> {
+        template
+            .render(index, record, &|value| Ok(value.replace('/', "_")))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_render_template() {
+        let record =
+            json!({"type": "dashboard", "id": 7, "attributes": {"title": "My Board: v2!"}});
+        let template: Template = "{type}/{attributes.title|slug}-{id}.json".parse().unwrap();
+        assert_eq!(
+            render(&template, 0, &record).as_deref(),
+            Some("dashboard/my-board-v2-7")
+        );
+
+        let template: Template = "{name,type|upper}_{#:3}{{x}}".parse().unwrap();
+        let template = template.starting_at(1);
+        assert_eq!(
+            render(&template, 4, &record).as_deref(),
+            Some("DASHBOARD_005{x}")
+        );
+
+        // Records missing a value without a fallback cannot be named by the template
+        let template: Template = "{name}-{#}".parse().unwrap();
+        assert_eq!(render(&template, 0, &record), None);
+        let template: Template = "{name,\"unnamed\"}-{#}".parse().unwrap();
+        assert_eq!(
+            render(&template, 12, &record).as_deref(),
+            Some("unnamed-000012")
+        );
+    }
+
+    #[test]
+    fn test_render_escapes_record_values() {
+        let record = json!({"type": "a/b", "title": "../up"});
+        let template: Template = "{type}/{title}/{x,\"c/d\"}".parse().unwrap();
+        assert_eq!(
+            render(&template, 0, &record).as_deref(),
+            Some("a_b/.._up/c/d")
+        );
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for template in ["{type", "type}", "{}", "{type|title}", "{#:x}", "{#x}"] {
+            assert!(template.parse::<Template>().is_err(), "{template}");
+        }
+    }
+}
@@ -154,3 +154,328 @@ fn test_bundle_command_stdout() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_bundle_many_files() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+
+    // More files than are read ahead at once
+    for i in 0..300 {
+        let content = json!({"name": format!("record-{i}"), "position": i});
+        fs::write(
+            input_dir.path().join(format!("record-{i}.json")),
+            content.to_string(),
+        )?;
+    }
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("bundle")
+        .arg(input_dir.path())
+        .arg("-")
+        .output()?;
+
+    assert!(
+        output.status.success(),
+        "Bundle command failed: {:?}",
+        output
+    );
+
+    let stdout_content = String::from_utf8_lossy(&output.stdout);
+    let mut positions: Vec<u64> = stdout_content
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|json| json["position"].as_u64().unwrap())
+        .collect();
+    positions.sort_unstable();
+
+    assert_eq!(positions, (0..300).collect::<Vec<u64>>());
+
+    Ok(())
+}
+
+#[test]
+fn test_bundle_compressed_output() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    create_input_files(&input_dir)?;
+
+    // The codec is picked from the output extension
+    let output_file = output_dir.path().join("letters.ndjson.zst");
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("bundle")
+        .arg(input_dir.path())
+        .arg(&output_file)
+        .assert()
+        .success();
+
+    let decoded = zstd::decode_all(fs::File::open(&output_file)?)?;
+    assert_eq!(String::from_utf8_lossy(&decoded).lines().count(), 6);
+
+    // Unbundling it again writes one gzip file per record
+    let unbundled_dir = output_dir.path().join("letters");
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--name=name")
+        .arg("--compress=gzip")
+        .arg("--compression-level=9")
+        .arg(&output_file)
+        .arg(&unbundled_dir)
+        .assert()
+        .success();
+
+    let mut decoder =
+        flate2::read::GzDecoder::new(fs::File::open(unbundled_dir.join("alpha.json.gz"))?);
+    let alpha: serde_json::Value = serde_json::from_reader(&mut decoder)?;
+    assert_eq!(alpha["position"], 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_bundle_json_seq_output() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    create_input_files(&input_dir)?;
+
+    let output_file = output_dir.path().join("letters.json-seq");
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("bundle")
+        .arg("--json-seq")
+        .arg(input_dir.path())
+        .arg(&output_file)
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&output_file)?;
+    assert_eq!(content.lines().count(), 6);
+    assert!(content.lines().all(|line| line.starts_with('\u{1e}')));
+
+    // The sequence unbundles back into the original records
+    let unbundled_dir = output_dir.path().join("letters");
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--name=name")
+        .arg(&output_file)
+        .arg(&unbundled_dir)
+        .assert()
+        .success();
+
+    let alpha: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(unbundled_dir.join("alpha.json"))?)?;
+    assert_eq!(alpha["position"], 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_bundle_multiple_inputs() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    create_input_files(&input_dir)?;
+    let loose_file = output_dir.path().join("golf.json");
+    fs::write(&loose_file, "{\"name\":\"golf\",\"position\":7}")?;
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("bundle")
+        .arg("--tag-source=_input")
+        .arg(input_dir.path())
+        .arg("--input")
+        .arg(&loose_file)
+        .arg("--input=-")
+        .write_stdin("{\"name\":\"hotel\",\"position\":8}\n")
+        .output()?;
+    assert!(output.status.success(), "Bundle failed: {:?}", output);
+
+    // Inputs are bundled in the order given, with stdin records passed through whole
+    let records: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 8);
+    let input_name = input_dir.path().to_str().unwrap();
+    assert!(records[..6]
+        .iter()
+        .all(|record| record["_input"] == input_name));
+    assert_eq!(
+        records[6],
+        json!({"name": "golf", "position": 7, "_input": loose_file.to_str().unwrap()})
+    );
+    assert_eq!(
+        records[7],
+        json!({"name": "hotel", "position": 8, "_input": "-"})
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_on_error_policies() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    fs::create_dir(input_dir.path().join("sub"))?;
+    fs::write(input_dir.path().join("alpha.json"), "{\"name\":\"alpha\"}")?;
+    fs::write(input_dir.path().join("bravo.json"), "{\"name\":")?;
+    fs::write(
+        input_dir.path().join("charlie.json"),
+        "{\"name\":\"charlie\"}",
+    )?;
+    fs::write(input_dir.path().join("sub/delta.json"), "not json")?;
+
+    let bundle = |args: &[&str]| {
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("bundle")
+            .args(args)
+            .arg(input_dir.path())
+            .output()
+            .unwrap()
+    };
+
+    // Failing stops at the first bad file, which is still summarized
+    let failed = bundle(&["--on-error=fail"]);
+    let stderr = String::from_utf8_lossy(&failed.stderr);
+    assert!(stderr.contains("1 file(s) could not be read"), "{stderr}");
+    assert!(stderr.contains("bravo.json"), "{stderr}");
+
+    let skipped = bundle(&["--on-error=skip"]);
+    assert!(skipped.status.success(), "Bundle failed: {:?}", skipped);
+    assert_eq!(
+        String::from_utf8_lossy(&skipped.stdout),
+        "{\"name\":\"alpha\"}\n{\"name\":\"charlie\"}\n"
+    );
+    let stderr = String::from_utf8_lossy(&skipped.stderr);
+    assert!(stderr.contains("1 file(s) could not be read"), "{stderr}");
+
+    // Quarantined files keep the path they were read from, so a file of another input with the
+    // same name does not overwrite them
+    let other_dir = tempdir()?;
+    fs::write(other_dir.path().join("bravo.json"), "{\"other\":")?;
+    let input_name = input_dir.path().file_name().unwrap();
+    let other_name = other_dir.path().file_name().unwrap();
+    let quarantine = output_dir.path().join("quarantine");
+    let quarantined = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .current_dir(input_dir.path().parent().unwrap())
+        .arg("merge")
+        .arg("--compact")
+        .arg("--recursive")
+        .arg("--on-error=quarantine")
+        .arg("--quarantine-dir")
+        .arg(&quarantine)
+        .arg(input_name)
+        .arg("--input")
+        .arg(other_name)
+        .output()?;
+    assert!(quarantined.status.success());
+    let merged: serde_json::Value = serde_json::from_slice(&quarantined.stdout)?;
+    assert_eq!(
+        merged,
+        json!({"alpha": {"name": "alpha"}, "charlie": {"name": "charlie"}, "sub": {}})
+    );
+    assert_eq!(
+        fs::read_to_string(quarantine.join(input_name).join("bravo.json"))?,
+        "{\"name\":"
+    );
+    assert_eq!(
+        fs::read_to_string(quarantine.join(input_name).join("sub/delta.json"))?,
+        "not json"
+    );
+    assert_eq!(
+        fs::read_to_string(quarantine.join(other_name).join("bravo.json"))?,
+        "{\"other\":"
+    );
+    let stderr = String::from_utf8_lossy(&quarantined.stderr);
+    assert!(stderr.contains("3 file(s) could not be read"), "{stderr}");
+
+    // Quarantining needs somewhere to put the files
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("bundle")
+        .arg("--on-error=quarantine")
+        .arg(input_dir.path())
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn test_bundle_unbundle_filenames() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    fs::create_dir(input_dir.path().join("sub"))?;
+    fs::write(input_dir.path().join("a.b.json"), "{\"name\":\"alpha\"}")?;
+    fs::write(
+        input_dir.path().join("sub/c.json"),
+        "{\"name\":\"charlie\"}",
+    )?;
+    fs::write(input_dir.path().join("sub/d.json"), "[4]")?;
+
+    for envelope in [None, Some("record")] {
+        let bundled = Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("bundle")
+            .arg("--recursive")
+            .arg("--filename-field=_file")
+            .args(envelope.map(|field| format!("--envelope={field}")))
+            .arg(input_dir.path())
+            .output()?;
+        assert!(bundled.status.success(), "Bundle failed: {:?}", bundled);
+        let mut lines: Vec<serde_json::Value> = String::from_utf8_lossy(&bundled.stdout)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        lines.sort_by_key(|line| line["_file"].as_str().map(str::to_string));
+        match envelope {
+            // Arrays cannot hold the field, so only an envelope keeps their path
+            None => assert_eq!(
+                lines,
+                vec![
+                    json!([4]),
+                    json!({"name": "alpha", "_file": "a.b.json"}),
+                    json!({"name": "charlie", "_file": "sub/c.json"}),
+                ]
+            ),
+            Some(_) => assert_eq!(
+                lines,
+                vec![
+                    json!({"_file": "a.b.json", "record": {"name": "alpha"}}),
+                    json!({"_file": "sub/c.json", "record": {"name": "charlie"}}),
+                    json!({"_file": "sub/d.json", "record": [4]}),
+                ]
+            ),
+        }
+
+        let unbundled_dir = output_dir.path().join(format!("{envelope:?}"));
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("unbundle")
+            .arg("--compact")
+            .arg("--filename-field=_file")
+            .args(envelope.map(|field| format!("--envelope={field}")))
+            .arg("-")
+            .arg(&unbundled_dir)
+            .write_stdin(bundled.stdout)
+            .assert()
+            .success();
+        assert_eq!(
+            fs::read_to_string(unbundled_dir.join("a.b.json"))?,
+            "{\"name\":\"alpha\"}"
+        );
+        assert_eq!(
+            fs::read_to_string(unbundled_dir.join("sub/c.json"))?,
+            "{\"name\":\"charlie\"}"
+        );
+        if envelope.is_some() {
+            assert_eq!(fs::read_to_string(unbundled_dir.join("sub/d.json"))?, "[4]");
+        }
+    }
+
+    Ok(())
+}
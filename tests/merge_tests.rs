@@ -41,6 +41,38 @@ fn test_merge_basic() -> std::io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_merge_sorted_pretty_layout() -> std::io::Result<()> {
+    let (input_dir, output_dir, files) = setup_merge_test()?;
+    let output_file = output_dir.path().join("merged.json");
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--sort")
+        .arg(input_dir.path())
+        .arg(&output_file)
+        .output()?;
+
+    assert!(
+        output.status.success(),
+        "Merge command with --sort failed: {:?}",
+        output
+    );
+
+    // The streamed object is laid out exactly like a pretty-printed merged value
+    let expected: serde_json::Map<String, serde_json::Value> = files
+        .into_iter()
+        .map(|(filename, content)| (filename.trim_end_matches(".json").to_string(), content))
+        .collect();
+    assert_eq!(
+        fs::read_to_string(&output_file)?,
+        serde_json::to_string_pretty(&expected)? + "\n"
+    );
+
+    Ok(())
+}
+
 // TODO: The --compact option is not yet implemented for the merge command
 // #[test]
 // fn test_merge_compact() -> std::io::Result<()> {
@@ -105,45 +137,185 @@ fn test_merge_filter() -> std::io::Result<()> {
     Ok(())
 }
 
-// TODO: The --trim option is not yet implemented for the merge command
-// #[test]
-// fn test_merge_trim() -> std::io::Result<()> {
-//     let (input_dir, output_dir, _) = setup_merge_test()?;
-//     let output_file = output_dir.path().join("merged.json");
+#[test]
+fn test_merge_compressed_files() -> std::io::Result<()> {
+    use std::io::Write;
 
-//     let output = Command::cargo_bin("jsrmx")
-//         .unwrap()
-//         .arg("merge")
-//         .arg("--trim")
-//         .arg(".json")
-//         .arg(input_dir.path())
-//         .arg(&output_file)
-//         .output()?;
+    let input_dir = tempdir()?;
+    let mut encoder = flate2::write::GzEncoder::new(
+        fs::File::create(input_dir.path().join("alpha.json.gz"))?,
+        flate2::Compression::default(),
+    );
+    encoder.write_all(br#"{"position": 1}"#)?;
+    encoder.finish()?;
+    fs::write(
+        input_dir.path().join("bravo.json.zst"),
+        zstd::encode_all(&br#"{"position": 2}"#[..], 0)?,
+    )?;
 
-//     assert!(
-//         output.status.success(),
-//         "Merge command with --trim failed: {:?}",
-//         output
-//     );
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--sort")
+        .arg(input_dir.path())
+        .output()?;
 
-//     let trimmed_content: serde_json::Value =
-//         serde_json::from_str(&fs::read_to_string(&output_file)?)?;
-//     let expected_content = json!({
-//         "alpha": {"uppercase": "A", "lowercase": "a", "position": 1},
-//         "bravo": {"uppercase": "B", "lowercase": "b", "position": 2},
-//         "charlie": {"uppercase": "C", "lowercase": "c", "position": 3},
-//         "delta": {"uppercase": "D", "lowercase": "d", "position": 4},
-//         "echo": {"uppercase": "E", "lowercase": "e", "position": 5},
-//         "foxtrot": {"uppercase": "F", "lowercase": "f", "position": 6},
-//     });
-
-//     assert_eq!(
-//         trimmed_content, expected_content,
-//         "Trimmed content should match the basic merge result"
-//     );
+    assert!(
+        output.status.success(),
+        "Merge of compressed files failed: {:?}",
+        output
+    );
 
-//     Ok(())
-// }
+    let merged_content: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(
+        merged_content,
+        json!({"alpha": {"position": 1}, "bravo": {"position": 2}})
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_lenient() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    fs::write(
+        input_dir.path().join("alpha.json"),
+        "{\n  // hand-edited\n  \"position\": 1,\n  tags: ['a', 'b',],\n}\n",
+    )?;
+    fs::write(input_dir.path().join("bravo.json"), r#"{"position": 2}"#)?;
+
+    // Strict parsing rejects the comment and trailing commas
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg(input_dir.path())
+        .output()?;
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error merging"));
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--lenient")
+        .arg("--sort")
+        .arg(input_dir.path())
+        .output()?;
+
+    assert!(
+        output.status.success(),
+        "Merge command with --lenient failed: {:?}",
+        output
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("alpha.json"), "No warning for alpha.json");
+    assert!(
+        !stderr.contains("bravo.json"),
+        "Unexpected warning: {stderr}"
+    );
+
+    let merged_content: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(
+        merged_content,
+        json!({"alpha": {"position": 1, "tags": ["a", "b"]}, "bravo": {"position": 2}})
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_fails_on_unreadable_file() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    fs::write(input_dir.path().join("a.json"), r#"{"a": 1}"#)?;
+    fs::write(input_dir.path().join("b.json"), "{bad")?;
+    fs::write(input_dir.path().join("c.json"), r#"{"c": 3}"#)?;
+    let output_file = output_dir.path().join("merged.json");
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--sort")
+        .arg("--compact")
+        .arg(input_dir.path())
+        .arg(&output_file)
+        .output()?;
+
+    assert!(!output.status.success(), "Merge should fail: {:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("b.json"));
+    // The object is left unclosed rather than passed off as complete
+    let written = fs::read_to_string(&output_file)?;
+    assert_eq!(written, r#"{"a":{"a":1}"#);
+    assert!(serde_json::from_str::<serde_json::Value>(&written).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_unreadable_input_file() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    fs::write(input_dir.path().join("a.json"), r#"{"a": 1}"#)?;
+    let bad_file = input_dir.path().join("bad.txt");
+    fs::write(&bad_file, "{bad")?;
+    let merge = |policy: &str| {
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("merge")
+            .arg("--compact")
+            .arg(policy)
+            .arg(input_dir.path())
+            .arg("--input")
+            .arg(&bad_file)
+            .assert()
+    };
+
+    // A file given as an input follows the error policy like the files of a directory do
+    merge("--on-error=skip")
+        .success()
+        .stdout("{\"a\":{\"a\":1}}\n");
+    merge("--on-error=fail")
+        .failure()
+        .stderr(predicates::str::contains("bad.txt"));
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_trim() -> std::io::Result<()> {
+    let (input_dir, output_dir, _) = setup_merge_test()?;
+    let output_file = output_dir.path().join("merged.json");
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--trim")
+        .arg(".json")
+        .arg(input_dir.path())
+        .arg(&output_file)
+        .output()?;
+
+    assert!(
+        output.status.success(),
+        "Merge command with --trim failed: {:?}",
+        output
+    );
+
+    let trimmed_content: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_file)?)?;
+    let expected_content = json!({
+        "alpha": {"uppercase": "A", "lowercase": "a", "position": 1},
+        "bravo": {"uppercase": "B", "lowercase": "b", "position": 2},
+        "charlie": {"uppercase": "C", "lowercase": "c", "position": 3},
+        "delta": {"uppercase": "D", "lowercase": "d", "position": 4},
+        "echo": {"uppercase": "E", "lowercase": "e", "position": 5},
+        "foxtrot": {"uppercase": "F", "lowercase": "f", "position": 6},
+    });
+
+    assert_eq!(
+        trimmed_content, expected_content,
+        "Trimmed content should match the basic merge result"
+    );
+
+    Ok(())
+}
 
 type MergeTestSetup = (
     tempfile::TempDir,
@@ -191,3 +363,411 @@ fn setup_merge_test() -> std::io::Result<MergeTestSetup> {
 
     Ok((input_dir, output_dir, files))
 }
+
+#[test]
+fn test_merge_array_index_order() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    // Indexes are ordered numerically, whether or not they are zero-padded
+    for i in 0..12 {
+        fs::write(input_dir.path().join(format!("{i}.json")), i.to_string())?;
+    }
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--array")
+        .arg("--compact")
+        .arg(input_dir.path())
+        .assert()
+        .success()
+        .stdout("[0,1,2,3,4,5,6,7,8,9,10,11]\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_sort_is_alphabetical() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    for key in ["10", "9", "1a", "b"] {
+        fs::write(
+            input_dir.path().join(format!("{key}.json")),
+            format!("\"{key}\""),
+        )?;
+    }
+    let merge = |flag: &str| {
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("merge")
+            .arg("--compact")
+            .arg(flag)
+            .arg(input_dir.path())
+            .assert()
+            .success()
+    };
+
+    // Only --array puts indexes in numeric order
+    merge("--sort").stdout("{\"10\":\"10\",\"1a\":\"1a\",\"9\":\"9\",\"b\":\"b\"}\n");
+    merge("--array").stdout("[\"9\",\"10\",\"1a\",\"b\"]\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_stdin_json_seq() -> std::io::Result<()> {
+    // Entries may arrive concatenated or as an RFC 7464 sequence rather than one per line
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--compact")
+        .arg("-")
+        .write_stdin("[\"a\",1][\"b\",{\"c\":2}]\n\u{1e}[\"d\",\n3]\n")
+        .assert()
+        .success()
+        .stdout("{\"a\":1,\"b\":{\"c\":2},\"d\":3}\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_stdin_from_split() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let input_file = input_dir.path().join("letters.json");
+    let letters = json!({"alpha": {"position": 1}, "bravo": [2], "charlie": "c"});
+    fs::write(&input_file, letters.to_string())?;
+
+    // `split` to stdout pretty-prints one single-key object per entry
+    let split = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("split")
+        .arg(&input_file)
+        .output()?;
+    assert!(split.status.success(), "Split failed: {:?}", split);
+
+    let merged = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--compact")
+        .arg("-")
+        .write_stdin(split.stdout)
+        .output()?;
+    assert!(merged.status.success(), "Merge failed: {:?}", merged);
+    assert_eq!(
+        String::from_utf8_lossy(&merged.stdout),
+        letters.to_string() + "\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_stdin_entry_forms() -> std::io::Result<()> {
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--compact")
+        .arg("-")
+        .write_stdin(concat!(
+            "{\"a\":1}\n",
+            "[\"b\",2]\n",
+            "{\"key\":\"c\",\"value\":3}\n",
+            "{\"d\":4,\"e\":5}\n",
+            "{\"key\":6,\"value\":6}\n",
+        ))
+        .output()?;
+
+    assert!(output.status.success(), "Merge failed: {:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{\"a\":1,\"b\":2,\"c\":3}\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("found an object with 2 keys") && stderr.contains("must be a string"),
+        "Expected both bad records to be reported: {stderr}"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_sorted_stdin() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    fs::write(input_dir.path().join("b.json"), "1")?;
+    fs::write(input_dir.path().join("d.json"), "1")?;
+
+    // Stdin entries are sorted before they are interleaved with the directory's
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--sort")
+        .arg("--compact")
+        .arg(input_dir.path())
+        .arg("--input=-")
+        .write_stdin("{\"z\":1}\n{\"a\":1}\n")
+        .assert()
+        .success()
+        .stdout("{\"a\":1,\"b\":1,\"d\":1,\"z\":1}\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_multiple_inputs() -> std::io::Result<()> {
+    let first_dir = tempdir()?;
+    let second_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    fs::write(first_dir.path().join("b.json"), "{\"from\":\"first\"}")?;
+    fs::write(first_dir.path().join("d.json"), "{\"from\":\"first\"}")?;
+    fs::write(second_dir.path().join("a.json"), "{\"from\":\"second\"}")?;
+    fs::write(second_dir.path().join("b.json"), "{\"from\":\"second\"}")?;
+    let loose_file = output_dir.path().join("c.json");
+    fs::write(&loose_file, "{\"from\":\"file\"}")?;
+
+    // The first input wins on a duplicate key, and sorting interleaves the inputs
+    let output_file = output_dir.path().join("merged.json");
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--compact")
+        .arg("--sort")
+        .arg(first_dir.path())
+        .arg("--input")
+        .arg(second_dir.path())
+        .arg("--input")
+        .arg(&loose_file)
+        .arg(&output_file)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&output_file)?,
+        concat!(
+            "{\"a\":{\"from\":\"second\"},\"b\":{\"from\":\"first\"},",
+            "\"c\":{\"from\":\"file\"},\"d\":{\"from\":\"first\"}}\n"
+        )
+    );
+
+    // Values can be tagged with their input
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--compact")
+        .arg("--tag-source=_input")
+        .arg(&loose_file)
+        .arg("--input=-")
+        .write_stdin("{\"e\":{\"from\":\"stdin\"}}\n")
+        .output()?;
+    assert!(output.status.success(), "Merge failed: {:?}", output);
+    let merged: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(
+        merged,
+        json!({
+            "c": {"from": "file", "_input": loose_file.to_str().unwrap()},
+            "e": {"from": "stdin", "_input": "-"},
+        })
+    );
+
+    // The path after the first input is always the output, and an existing one is replaced
+    for _ in 0..2 {
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("merge")
+            .arg("--compact")
+            .arg("--sort")
+            .arg(first_dir.path())
+            .arg(&loose_file)
+            .assert()
+            .success();
+    }
+    assert_eq!(
+        fs::read_to_string(&loose_file)?,
+        "{\"b\":{\"from\":\"first\"},\"d\":{\"from\":\"first\"}}\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_recursive() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    fs::create_dir_all(input_dir.path().join("a/c"))?;
+    fs::write(input_dir.path().join("top.json"), "1")?;
+    fs::write(input_dir.path().join("a/b.json"), "2")?;
+    fs::write(input_dir.path().join("a/c/d.json"), "3")?;
+
+    let merge = |args: &[&str]| {
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("merge")
+            .arg("--compact")
+            .arg("--sort")
+            .args(args)
+            .arg(input_dir.path())
+            .assert()
+            .success()
+    };
+
+    // Without --recursive, subdirectories are skipped instead of failing the merge
+    merge(&[]).stdout("{\"top\":1}\n");
+    merge(&["--recursive"]).stdout("{\"a\":{\"b\":2,\"c\":{\"d\":3}},\"top\":1}\n");
+    merge(&["--recursive", "--raw"]).stdout("{\"a\":{\"b\":2,\"c\":{\"d\":3}},\"top\":1}\n");
+    merge(&["--recursive", "--flatten"]).stdout("{\"a/b\":2,\"a/c/d\":3,\"top\":1}\n");
+    merge(&["--recursive", "--max-depth=1"]).stdout("{\"a\":{\"b\":2},\"top\":1}\n");
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_merge_recursive_symlink_loop() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    fs::create_dir(input_dir.path().join("a"))?;
+    fs::write(input_dir.path().join("a/b.json"), "2")?;
+    std::os::unix::fs::symlink(input_dir.path(), input_dir.path().join("a/loop"))?;
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--compact")
+        .arg("--recursive")
+        .arg(input_dir.path())
+        .assert()
+        .success()
+        .stdout("{\"a\":{\"b\":2}}\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_include_exclude() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    fs::create_dir(input_dir.path().join("sub"))?;
+    fs::write(input_dir.path().join("alpha.json"), "1")?;
+    fs::write(input_dir.path().join("bravo.json5"), "2")?;
+    fs::write(input_dir.path().join("draft.json"), "3")?;
+    fs::write(input_dir.path().join("sub/charlie.json"), "4")?;
+    fs::write(input_dir.path().join("README.md"), "# Not JSON")?;
+    fs::write(input_dir.path().join(".gitkeep"), "")?;
+
+    let merge = |args: &[&str]| {
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("merge")
+            .arg("--compact")
+            .arg("--sort")
+            .arg("--recursive")
+            .args(args)
+            .arg(input_dir.path())
+            .assert()
+            .success()
+    };
+
+    merge(&[]).stdout("{\"alpha\":1,\"draft\":3,\"sub\":{\"charlie\":4}}\n");
+    merge(&["--include=*.json5", "--include=*.json"])
+        .stdout("{\"alpha\":1,\"bravo\":2,\"draft\":3,\"sub\":{\"charlie\":4}}\n");
+    merge(&["--exclude=draft*", "--exclude=sub/"]).stdout("{\"alpha\":1}\n");
+
+    fs::write(
+        input_dir.path().join(".jsrmxignore"),
+        "# Work in progress\ndraft.json\n\nsub/charlie.json\n",
+    )?;
+    merge(&[]).stdout("{\"alpha\":1,\"sub\":{}}\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_key_derivation() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    fs::write(
+        input_dir.path().join("a.b.json"),
+        "{\"id\":\"alpha\",\"n\":1}",
+    )?;
+    fs::write(
+        input_dir.path().join("c.d.json"),
+        "{\"id\":\"charlie\",\"n\":2}",
+    )?;
+
+    let merge = |args: &[&str]| {
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("merge")
+            .arg("--compact")
+            .arg("--sort")
+            .args(args)
+            .arg(input_dir.path())
+            .assert()
+    };
+
+    let values = |a: &str, c: &str| {
+        format!("{{\"{a}\":{{\"id\":\"alpha\",\"n\":1}},\"{c}\":{{\"id\":\"charlie\",\"n\":2}}}}\n")
+    };
+    merge(&[]).success().stdout(values("a.b", "c.d"));
+    merge(&["--trim=.b.json"])
+        .success()
+        .stdout(values("a", "c.d.json"));
+    merge(&["--full-name"])
+        .success()
+        .stdout(values("a.b.json", "c.d.json"));
+    merge(&["--key-regex=^(\\w+)\\."])
+        .success()
+        .stdout(values("a", "c"));
+    merge(&["--key-regex=\\.(?<key>\\w+)\\.json$"])
+        .success()
+        .stdout(values("b", "d"));
+    merge(&["--key-field=id"])
+        .success()
+        .stdout(values("alpha", "charlie"));
+    merge(&["--key-field=id", "--raw"])
+        .success()
+        .stdout(values("alpha", "charlie"));
+    merge(&["--trim=.json", "--full-name"]).failure();
+    merge(&["--key-regex=json"]).failure();
+
+    // Keys derived twice keep the first file, or fail the merge with --unique-keys
+    merge(&["--key-regex=(json)"])
+        .success()
+        .stdout("{\"json\":{\"id\":\"alpha\",\"n\":1}}\n");
+    merge(&["--key-regex=(json)", "--unique-keys"])
+        .failure()
+        .stderr(predicates::str::contains("Duplicate key json"));
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_key_field_order() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    fs::write(input_dir.path().join("a.json"), "{\"id\":\"charlie\"}")?;
+    fs::write(input_dir.path().join("m.json"), "{\"id\":\"bravo\"}")?;
+    fs::write(input_dir.path().join("z.json"), "{\"id\":\"alpha\"}")?;
+
+    // Entries are ordered by the key read from each file, not by the file name
+    let merge = |args: &[&str]| {
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("merge")
+            .arg("--compact")
+            .arg("--key-field=id")
+            .args(args)
+            .arg(input_dir.path())
+            .assert()
+    };
+    merge(&["--sort"]).success().stdout(concat!(
+        "{\"alpha\":{\"id\":\"alpha\"},\"bravo\":{\"id\":\"bravo\"},",
+        "\"charlie\":{\"id\":\"charlie\"}}\n"
+    ));
+    merge(&["--array", "--raw"])
+        .success()
+        .stdout("[{\"id\":\"alpha\"},{\"id\":\"bravo\"},{\"id\":\"charlie\"}]\n");
+
+    // A file that cannot be parsed for its key is still left to the error policy
+    fs::write(input_dir.path().join("broken.json"), "{\"id\":")?;
+    merge(&["--array", "--on-error=skip"])
+        .success()
+        .stdout("[{\"id\":\"alpha\"},{\"id\":\"bravo\"},{\"id\":\"charlie\"}]\n");
+    merge(&["--sort"])
+        .failure()
+        .stderr(predicates::str::contains("broken.json"));
+
+    Ok(())
+}
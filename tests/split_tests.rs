@@ -69,10 +69,260 @@ fn test_split_stdin_to_stdout() -> std::io::Result<()> {
 //     Ok(())
 // }
 
-// TODO: The --filter option is not yet implemented for the split command
-// #[test]
-// fn test_split_filter() -> std::io::Result<()> {
-//     // Similar to test_split_stdin_to_stdout, but add --filter flag
-//     // Check that only filtered keys are in the output
-//     Ok(())
-// }
+#[test]
+fn test_split_filter() -> std::io::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let input_file = temp_dir.path().join("input.json");
+    let output_dir = temp_dir.path().join("output");
+
+    let input_json = json!({
+        "alpha": {"uppercase": "A", "lowercase": "a", "position": 1},
+        "bravo": {"uppercase": "B", "lowercase": "b", "position": 2},
+        "charlie": {"uppercase": "C", "lowercase": "c", "position": 3}
+    });
+    std::fs::write(&input_file, serde_json::to_string_pretty(&input_json)?)?;
+
+    let _ = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("split")
+        .arg("--filter")
+        .arg("alpha|charlie")
+        .arg(&input_file)
+        .arg(&output_dir)
+        .assert()
+        .success();
+
+    assert!(output_dir.join("alpha.json").exists());
+    assert!(!output_dir.join("bravo.json").exists());
+    assert!(output_dir.join("charlie.json").exists());
+
+    let charlie_content: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(output_dir.join("charlie.json"))?)?;
+    assert_eq!(charlie_content, input_json["charlie"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_split_merge_raw_round_trip() -> std::io::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let input_file = temp_dir.path().join("input.json");
+    let output_dir = temp_dir.path().join("output");
+
+    let input = r#"{"alpha": {"price": 1.50, "name": "caf\u00e9"}, "bravo": [1e3,  2]}"#;
+    std::fs::write(&input_file, input)?;
+
+    let _ = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("split")
+        .arg("--raw")
+        .arg(&input_file)
+        .arg(&output_dir)
+        .assert()
+        .success();
+
+    assert_eq!(
+        std::fs::read_to_string(output_dir.join("alpha.json"))?,
+        r#"{"price": 1.50, "name": "caf\u00e9"}"#
+    );
+    assert_eq!(
+        std::fs::read_to_string(output_dir.join("bravo.json"))?,
+        "[1e3,  2]"
+    );
+
+    let _ = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--raw")
+        .arg("--compact")
+        .arg("--sort")
+        .arg(&output_dir)
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}\n",
+            r#"{"alpha":{"price": 1.50, "name": "caf\u00e9"},"bravo":[1e3,  2]}"#
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn test_split_keeps_key_order() -> std::io::Result<()> {
+    let input = r#"{"foxtrot": {"uppercase": "F", "lowercase": "f"}, "alpha": {"uppercase": "A", "lowercase": "a"}}"#;
+
+    let _ = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("split")
+        .arg("--compact")
+        .arg("-")
+        .arg("-")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(concat!(
+            r#"{"foxtrot":{"uppercase":"F","lowercase":"f"}}"#,
+            "\n",
+            r#"{"alpha":{"uppercase":"A","lowercase":"a"}}"#,
+            "\n"
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn test_split_array_root() -> std::io::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let input_file = temp_dir.path().join("input.json");
+    let by_index = temp_dir.path().join("by_index");
+    let by_name = temp_dir.path().join("by_name");
+
+    let input_json = json!([
+        {"name": "alpha", "position": 1},
+        {"name": "bravo", "position": 2},
+        {"position": 3}
+    ]);
+    std::fs::write(&input_file, serde_json::to_string_pretty(&input_json)?)?;
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("split")
+        .arg(&input_file)
+        .arg(&by_index)
+        .assert()
+        .success();
+
+    for (i, element) in input_json.as_array().unwrap().iter().enumerate() {
+        let content: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(
+            by_index.join(format!("{i:06}.json")),
+        )?)?;
+        assert_eq!(&content, element);
+    }
+
+    // Elements without the name field fall back to their index
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("split")
+        .arg("--name=name")
+        .arg(&input_file)
+        .arg(&by_name)
+        .assert()
+        .success();
+
+    assert!(by_name.join("alpha.json").exists());
+    assert!(by_name.join("bravo.json").exists());
+    assert!(by_name.join("000002.json").exists());
+
+    // Merging the elements back rebuilds the array in index order
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("merge")
+        .arg("--array")
+        .arg(&by_index)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "Merge --array failed: {:?}",
+        output
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        serde_json::to_string_pretty(&input_json)? + "\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_split_depth() -> std::io::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let input_file = temp_dir.path().join("input.json");
+    let input = r#"{"a": {"b": 1, "c": {"d": 2}, "e": {}}, "f": [3]}"#;
+    std::fs::write(&input_file, input)?;
+
+    for raw in [false, true] {
+        let output_dir = temp_dir.path().join(format!("output-{raw}"));
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("split")
+            .arg("--compact")
+            .arg("--depth=1")
+            .args(raw.then_some("--raw"))
+            .arg(&input_file)
+            .arg(&output_dir)
+            .assert()
+            .success();
+
+        // Splitting stops at the depth limit, at empty objects and at non-object values
+        let read = |path: &str| std::fs::read_to_string(output_dir.join(path)).unwrap();
+        assert_eq!(read("a/b.json"), "1");
+        assert_eq!(read("a/c.json").replace(' ', ""), r#"{"d":2}"#);
+        assert_eq!(read("a/e.json"), "{}");
+        assert_eq!(read("f.json"), "[3]");
+
+        // A recursive merge puts the tree back together
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("merge")
+            .arg("--compact")
+            .arg("--recursive")
+            .arg("--sort")
+            .arg(&output_dir)
+            .assert()
+            .success()
+            .stdout("{\"a\":{\"b\":1,\"c\":{\"d\":2},\"e\":{}},\"f\":[3]}\n");
+    }
+
+    // Splitting to stdout names each entry by its path
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("split")
+        .arg("--compact")
+        .arg("--depth=2")
+        .arg(&input_file)
+        .assert()
+        .success()
+        .stdout("{\"a/b\":1}\n{\"a/c/d\":2}\n{\"a/e\":{}}\n{\"f\":[3]}\n");
+
+    Ok(())
+}
+
+#[test]
+fn test_split_escapes_slashes_in_keys() -> std::io::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let input_file = temp_dir.path().join("input.json");
+    std::fs::write(&input_file, r#"{"a/b": 1, "c": {"d/e": 2}}"#)?;
+
+    for raw in [false, true] {
+        let output_dir = temp_dir.path().join(format!("output-{raw}"));
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("split")
+            .arg("--compact")
+            .arg("--depth=1")
+            .args(raw.then_some("--raw"))
+            .arg(&input_file)
+            .arg(&output_dir)
+            .assert()
+            .success();
+
+        // Only --depth starts a subdirectory, a `/` inside a key does not
+        let read = |path: &str| std::fs::read_to_string(output_dir.join(path)).unwrap();
+        assert_eq!(read("a_b.json"), "1");
+        assert_eq!(read("c/d_e.json"), "2");
+        assert!(!output_dir.join("a").exists());
+    }
+
+    // In strict mode the key is refused
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("split")
+        .arg("--strict-names")
+        .arg(&input_file)
+        .arg(temp_dir.path().join("strict"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Refusing to write"));
+
+    Ok(())
+}
@@ -109,4 +109,639 @@ fn test_unbundle_command() -> std::io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_unbundle_keeps_input_order() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+
+    // Enough records to span several read batches
+    let input_content: Vec<serde_json::Value> = (0..2500)
+        .map(|i| json!({"name": format!("record-{i}"), "position": i}))
+        .collect();
+    let input_file = input_dir.path().join("records.ndjson");
+    fs::write(
+        &input_file,
+        input_content
+            .iter()
+            .map(|j| j.to_string() + "\n")
+            .collect::<String>(),
+    )?;
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--compact")
+        .arg(&input_file)
+        .arg("-")
+        .output()?;
+
+    assert!(
+        output.status.success(),
+        "Unbundle to stdout failed: {:?}",
+        output
+    );
+
+    let stdout_content = String::from_utf8_lossy(&output.stdout);
+    let output_lines: Vec<serde_json::Value> = stdout_content
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let expected_lines: Vec<serde_json::Value> = input_content
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| json!({ format!("object-{i:06}"): entry }))
+        .collect();
+    assert_eq!(output_lines, expected_lines, "Stdout order changed");
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg(&input_file)
+        .arg(output_dir.path())
+        .output()?;
+
+    assert!(
+        output.status.success(),
+        "Unbundle to directory failed: {:?}",
+        output
+    );
+
+    for (i, entry) in input_content.iter().enumerate() {
+        let file_path = output_dir.path().join(format!("object-{i:06}.json"));
+        let file_content: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&file_path)?)?;
+        assert_eq!(&file_content, entry, "Content of {:?} changed", file_path);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_duplicate_names() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+
+    // Every record of a parallel batch is named the same, and the last one read wins
+    let input_file = input_dir.path().join("records.ndjson");
+    fs::write(
+        &input_file,
+        (0..1500)
+            .map(|i| json!({"name": "same", "position": i}).to_string() + "\n")
+            .collect::<String>(),
+    )?;
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .env("RAYON_NUM_THREADS", "8")
+        .arg("unbundle")
+        .arg("--compact")
+        .arg("--name=name")
+        .arg(&input_file)
+        .arg(output_dir.path())
+        .assert()
+        .success();
+
+    let file_content: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(output_dir.path().join("same.json"))?)?;
+    assert_eq!(file_content, json!({"name": "same", "position": 1499}));
+    assert_eq!(fs::read_dir(output_dir.path())?.count(), 1);
+
+    Ok(())
+}
+
 // TODO: Add tests for the --compact option
+
+#[test]
+fn test_unbundle_closed_stdout() -> std::io::Result<()> {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let input_dir = tempdir()?;
+    let input_file = input_dir.path().join("many.ndjson");
+    fs::write(&input_file, "{\"position\":1}\n".repeat(200_000))?;
+
+    // Read a single line and close the pipe, like `jsrmx unbundle ... | head -1`
+    let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin("jsrmx"))
+        .arg("unbundle")
+        .arg("--compact")
+        .arg(&input_file)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut first_line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut first_line)?;
+    let output = child.wait_with_output()?;
+
+    assert_eq!(first_line, "{\"object-000000\":{\"position\":1}}\n");
+    assert!(output.status.success(), "Unbundle failed: {:?}", output);
+    assert!(
+        output.stderr.is_empty(),
+        "Unexpected errors: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_gzip_input() -> std::io::Result<()> {
+    use std::io::Write;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let input_file = input_dir.path().join("letters.ndjson.gz");
+    let mut encoder = flate2::write::GzEncoder::new(
+        fs::File::create(&input_file)?,
+        flate2::Compression::default(),
+    );
+    encoder.write_all(b"{\"name\":\"alpha\"}\n{\"name\":\"bravo\"}\n")?;
+    encoder.finish()?;
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--name=name")
+        .arg(&input_file)
+        .arg(output_dir.path())
+        .assert()
+        .success();
+
+    for name in ["alpha", "bravo"] {
+        let content: serde_json::Value = serde_json::from_str(&fs::read_to_string(
+            output_dir.path().join(format!("{name}.json")),
+        )?)?;
+        assert_eq!(content, json!({"name": name}));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_fails_on_truncated_gzip_input() -> std::io::Result<()> {
+    use std::io::Write;
+
+    let input_dir = tempdir()?;
+    let input_file = input_dir.path().join("numbers.ndjson.gz");
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    for n in 0..10000 {
+        writeln!(encoder, "{{\"n\":{n}}}")?;
+    }
+    let compressed = encoder.finish()?;
+    fs::write(&input_file, &compressed[..compressed.len() / 2])?;
+
+    // A corrupt stream is a read error, not the end of input
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--compact")
+        .arg(&input_file)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Error reading input"));
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_concatenated_and_json_seq_input() -> std::io::Result<()> {
+    let inputs = [
+        // Back-to-back objects, with braces inside strings
+        "{\"name\":\"a}{\"}{\"name\":\"b\"}\n{\"name\":\"c\"}",
+        // An RFC 7464 JSON text sequence
+        "\u{1e}{\"name\":\"a}{\"}\n\u{1e}{\"name\":\"b\"}\n\u{1e}{\"name\":\"c\"}\n",
+    ];
+    for input in inputs {
+        let output = Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("unbundle")
+            .arg("--compact")
+            .write_stdin(input)
+            .output()?;
+
+        assert!(output.status.success(), "Unbundle failed: {:?}", output);
+        let output_lines: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            output_lines,
+            vec![
+                json!({"object-000000": {"name": "a}{"}}),
+                json!({"object-000001": {"name": "b"}}),
+                json!({"object-000002": {"name": "c"}}),
+            ],
+            "Unexpected records from {input:?}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_pretty_printed_input() -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let input_file = input_dir.path().join("letters.json");
+    let records = [
+        serde_json::to_string_pretty(&json!({"name": "alpha"}))?,
+        "{\n  \"name\": \n}".to_string(),
+        serde_json::to_string_pretty(&json!({"name": "charlie"}))?,
+    ];
+    fs::write(&input_file, records.join("\n"))?;
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--compact")
+        .arg(&input_file)
+        .output()?;
+
+    assert!(output.status.success(), "Unbundle failed: {:?}", output);
+    // Records are named by the line they start on, and the broken one is reported by it too
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "{\"object-000000\":{\"name\":\"alpha\"}}\n{\"object-000006\":{\"name\":\"charlie\"}}\n"
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("starting on line 3"),
+        "Expected the broken record to be reported: {:?}",
+        output
+    );
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_compact_then_pretty_printed_input() -> std::io::Result<()> {
+    // The first record does not decide how the rest are read
+    assert_unbundled(
+        "{\"a\":1}\n{\n  \"b\": 2\n}\n{\"c\":3}{\"d\":4}\n",
+        false,
+        concat!(
+            "{\"object-000000\":{\"a\":1}}\n",
+            "{\"object-000001\":{\"b\":2}}\n",
+            "{\"object-000004\":{\"c\":3}}\n",
+            "{\"object-000005\":{\"d\":4}}\n",
+        ),
+    )
+}
+
+#[test]
+fn test_unbundle_mixed_input_across_chunks() -> std::io::Result<()> {
+    // Large enough to be split into several chunks when read from a file, with records that are
+    // not one per line in the middle of it. Each record's text is paired with what it unbundles to.
+    let pad = "x".repeat(64);
+    let mut records: Vec<(String, Option<String>)> = Vec::new();
+    for n in 0..40_000 {
+        let short = format!("{{\"n\":{n}}}");
+        match n {
+            15_000 => records.push((format!("{{\n  \"n\": {n}\n}}\n"), Some(short))),
+            // Reported and skipped, without taking the next record with it
+            20_000 => records.push(("{\"n\":\n".to_string(), None)),
+            // Two records on one line
+            25_000 => {
+                records.push((short.clone(), Some(short.clone())));
+                records.push((format!("{short}\n"), Some(short)));
+            }
+            _ => {
+                let json = format!("{{\"n\":{n},\"pad\":\"{pad}\"}}");
+                records.push((format!("{json}\n"), Some(json)));
+            }
+        }
+    }
+
+    let mut input = String::new();
+    let mut expected = String::new();
+    let mut next = 0;
+    let mut line = 0;
+    for (text, json) in records {
+        if let Some(json) = json {
+            let number = usize::max(next, line);
+            expected.push_str(&format!("{{\"object-{number:06}\":{json}}}\n"));
+            next = number + 1;
+        }
+        line += text.matches('\n').count();
+        input.push_str(&text);
+    }
+    assert_unbundled(&input, false, &expected)
+}
+
+/// Unbundles `input` to stdout with `--compact`, both from a file and from stdin, since they are
+/// read by different code paths.
+fn assert_unbundled(input: &str, raw: bool, expected: &str) -> std::io::Result<()> {
+    let input_dir = tempdir()?;
+    let input_file = input_dir.path().join("input.ndjson");
+    fs::write(&input_file, input)?;
+
+    let mut from_file = Command::cargo_bin("jsrmx").unwrap();
+    from_file.arg("unbundle").arg("--compact").arg(&input_file);
+    let mut from_stdin = Command::cargo_bin("jsrmx").unwrap();
+    from_stdin
+        .arg("unbundle")
+        .arg("--compact")
+        .write_stdin(input);
+    for mut command in [from_file, from_stdin] {
+        if raw {
+            command.arg("--raw");
+        }
+        command.assert().success().stdout(expected.to_string());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_skips_blank_lines() -> std::io::Result<()> {
+    // Records keep the number of the line they are on
+    assert_unbundled(
+        "\n{\"a\":1}\n\n  \t\n{\"b\":2}\n\n",
+        false,
+        "{\"object-000001\":{\"a\":1}}\n{\"object-000004\":{\"b\":2}}\n",
+    )
+}
+
+#[test]
+fn test_unbundle_crlf_line_endings() -> std::io::Result<()> {
+    let expected = "{\"object-000000\":{\"a\":1}}\n{\"object-000001\":{\"b\":2}}\n";
+    assert_unbundled("{\"a\":1}\r\n{\"b\":2}\r\n", false, expected)?;
+    assert_unbundled("{\"a\":1}\r\n{\"b\":2}\r\n", true, expected)
+}
+
+#[test]
+fn test_unbundle_missing_final_newline() -> std::io::Result<()> {
+    assert_unbundled(
+        "{\"a\":1}\n{\"b\":2}",
+        false,
+        "{\"object-000000\":{\"a\":1}}\n{\"object-000001\":{\"b\":2}}\n",
+    )
+}
+
+#[test]
+fn test_unbundle_continues_after_truncated_line() -> std::io::Result<()> {
+    // A cut-off record is reported, but does not end the input
+    assert_unbundled(
+        "{\"a\":1}\n{\"b\":\n{\"c\":3}\n",
+        false,
+        "{\"object-000000\":{\"a\":1}}\n{\"object-000002\":{\"c\":3}}\n",
+    )
+}
+
+#[test]
+fn test_unbundle_continues_after_invalid_utf8_line() -> std::io::Result<()> {
+    let input: &[u8] = b"{\"a\":1}\n\xff\n{\"b\":2}\n";
+    let input_dir = tempdir()?;
+    let input_file = input_dir.path().join("input.ndjson");
+    fs::write(&input_file, input)?;
+
+    let mut from_file = Command::cargo_bin("jsrmx").unwrap();
+    from_file.arg("unbundle").arg("--compact").arg(&input_file);
+    let mut from_stdin = Command::cargo_bin("jsrmx").unwrap();
+    from_stdin
+        .arg("unbundle")
+        .arg("--compact")
+        .write_stdin(input);
+    for mut command in [from_file, from_stdin] {
+        command
+            .assert()
+            .success()
+            .stdout("{\"object-000000\":{\"a\":1}}\n{\"object-000002\":{\"b\":2}}\n")
+            .stderr(predicates::str::contains("starting on line 1"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_filename_template() -> std::io::Result<()> {
+    let output_dir = tempdir()?;
+    let input = concat!(
+        r#"{"type":"dashboard","id":"a1","attributes":{"title":"Web Traffic"}}"#,
+        "\n",
+        r#"{"type":"visualization","id":"b2","attributes":{"title":"Top URLs (24h)"}}"#,
+        "\n",
+        r#"{"type":"config","id":"c3","attributes":{}}"#,
+        "\n",
+        r#"{"id":"d4"}"#,
+        "\n",
+    );
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--compact")
+        .arg("--filename={type}/{attributes.title,id|slug}-{#:2}.json")
+        .arg("--index-start=1")
+        .arg("-")
+        .arg(output_dir.path())
+        .write_stdin(input)
+        .assert()
+        .success();
+
+    for (path, id) in [
+        ("dashboard/web-traffic-01.json", "a1"),
+        ("visualization/top-urls-24h-02.json", "b2"),
+        ("config/c3-03.json", "c3"),
+        // Records without a type cannot fill in the template, so they keep the default name
+        ("object-000003.json", "d4"),
+    ] {
+        let content: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(output_dir.path().join(path))?)?;
+        assert_eq!(content["id"], id, "{path}");
+    }
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--filename={type|title}")
+        .arg("-")
+        .write_stdin(input)
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_template_index_counts_records() -> std::io::Result<()> {
+    let output_dir = tempdir()?;
+    let input = "\n{\n  \"id\": \"a\"\n}\n\n{\"id\":\"b\"}{\"id\":\"c\"}\n";
+
+    // `{#}` counts records, while default names keep the line each record starts on
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--filename={id}-{#:2}")
+        .arg("-")
+        .arg(output_dir.path())
+        .write_stdin(input)
+        .assert()
+        .success();
+    for name in ["a-00.json", "b-01.json", "c-02.json"] {
+        assert!(output_dir.path().join(name).exists(), "{name}");
+    }
+
+    assert_unbundled(
+        input,
+        false,
+        concat!(
+            "{\"object-000001\":{\"id\":\"a\"}}\n",
+            "{\"object-000005\":{\"id\":\"b\"}}\n",
+            "{\"object-000006\":{\"id\":\"c\"}}\n",
+        ),
+    )
+}
+
+#[test]
+fn test_unbundle_unsafe_filenames() -> std::io::Result<()> {
+    let temp_dir = tempdir()?;
+    let output_dir = temp_dir.path().join("output");
+    let input = concat!(
+        r#"{"name":"../../escaped"}"#,
+        "\n",
+        r#"{"name":"/tmp/absolute"}"#,
+        "\n",
+        r#"{"name":"nul\u0000byte"}"#,
+        "\n",
+        r#"{"name":"x","_file":"../../escaped.json"}"#,
+        "\n",
+        r#"{"name":"y","_file":"/tmp/absolute.json"}"#,
+        "\n",
+    );
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--name=name")
+        .arg("--filename-field=_file")
+        .arg("-")
+        .arg(&output_dir)
+        .write_stdin(input)
+        .assert()
+        .success();
+
+    // A `/` in a name read from a record is escaped, so it cannot start a subdirectory
+    assert!(output_dir.join(".._.._escaped.json").exists());
+    assert!(output_dir.join("_tmp_absolute.json").exists());
+    assert!(output_dir.join("nul_byte.json").exists());
+    // Restored paths keep their subdirectories, but are rewritten to stay inside the output
+    assert!(output_dir.join("__/__/escaped.json").exists());
+    assert!(output_dir.join("tmp/absolute.json").exists());
+    assert!(!temp_dir.path().join("escaped.json").exists());
+
+    // In strict mode the unsafe names are refused instead, and the command fails
+    for (unsafe_record, args) in [
+        (r#"{"name":"a/b"}"#, &["--name=name"][..]),
+        (
+            r#"{"_file":"../escaped.json"}"#,
+            &["--filename-field=_file"][..],
+        ),
+        (r#"{"type":"a/b"}"#, &["--filename={type}/{#}"][..]),
+    ] {
+        let strict_dir = tempdir()?;
+        Command::cargo_bin("jsrmx")
+            .unwrap()
+            .arg("unbundle")
+            .args(args)
+            .arg("--strict-names")
+            .arg("-")
+            .arg(strict_dir.path())
+            .write_stdin(format!(
+                "{unsafe_record}\n{{\"name\":\"safe\",\"type\":\"t\"}}\n"
+            ))
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("Refusing to write"));
+        assert_eq!(
+            fs::read_dir(strict_dir.path())?.count(),
+            1,
+            "{unsafe_record}"
+        );
+    }
+    assert!(!temp_dir.path().join("escaped.json").exists());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unbundle_refuses_symlinks_out_of_output() -> std::io::Result<()> {
+    let temp_dir = tempdir()?;
+    let output_dir = temp_dir.path().join("output");
+    let outside = temp_dir.path().join("outside");
+    fs::create_dir_all(&output_dir)?;
+    fs::create_dir_all(&outside)?;
+    std::os::unix::fs::symlink(&outside, output_dir.join("link"))?;
+    std::os::unix::fs::symlink(outside.join("target.json"), output_dir.join("file.json"))?;
+    fs::create_dir(output_dir.join("inside"))?;
+    std::os::unix::fs::symlink(output_dir.join("inside"), output_dir.join("alias"))?;
+
+    let output = Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--filename-field=_file")
+        .arg("-")
+        .arg(&output_dir)
+        .write_stdin(concat!(
+            r#"{"_file":"link/x.json"}"#,
+            "\n",
+            r#"{"_file":"file.json"}"#,
+            "\n",
+            r#"{"_file":"alias/y.json"}"#,
+            "\n"
+        ))
+        .output()?;
+
+    assert!(
+        !output.status.success(),
+        "Unbundle should fail: {:?}",
+        output
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("outside the output directory"), "{stderr}");
+    assert_eq!(fs::read_dir(&outside)?.count(), 0);
+    // Symlinks that stay inside the output directory are still followed
+    assert!(output_dir.join("inside/y.json").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_fails_on_unwritable_file() -> std::io::Result<()> {
+    let output_dir = tempdir()?;
+    // A directory stands where the first record's file would go
+    fs::create_dir(output_dir.path().join("object-000000.json"))?;
+
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("-")
+        .arg(output_dir.path())
+        .write_stdin("{\"a\":1}\n{\"b\":2}\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("object-000000.json"));
+    // The other records are still written
+    assert!(output_dir.path().join("object-000001.json").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_unbundle_restores_exact_paths() -> std::io::Result<()> {
+    let output_dir = tempdir()?;
+
+    // A restored path keeps whatever extension it had, or none
+    Command::cargo_bin("jsrmx")
+        .unwrap()
+        .arg("unbundle")
+        .arg("--compact")
+        .arg("--filename-field=_file")
+        .arg("-")
+        .arg(output_dir.path())
+        .write_stdin("{\"_file\":\"sub/cfg.json5\",\"a\":1}\n{\"_file\":\"notes\",\"b\":2}\n")
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(output_dir.path().join("sub/cfg.json5"))?,
+        "{\"a\":1}"
+    );
+    assert_eq!(
+        fs::read_to_string(output_dir.path().join("notes"))?,
+        "{\"b\":2}"
+    );
+    assert!(!output_dir.path().join("sub/cfg.json5.json").exists());
+    Ok(())
+}